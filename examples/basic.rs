@@ -10,26 +10,27 @@
 
 extern crate djinn;
 extern crate redis;
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
 
 use std::thread;
-use redis::{Client, Commands};
-use djinn::{Agent, Manager, Simulation, Population, Updates, Redis, WebSocketServer, run};
+use redis::Client;
+use djinn::{Agent, Manager, Simulation, Population, Updates, Backend, WebSocketServer, run, MessagePack};
 
 const HEALTH_START: usize = 10;
 const HEALTH_CHANGE: usize = 10;
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct State {
     health: usize,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct World {
     weather: String,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum Update {
     ChangeHealth(usize),
 }
@@ -41,11 +42,12 @@ impl Simulation for BasicSim {
     type State = State;
     type Update = Update;
     type World = World;
+    type Codec = MessagePack;
 
-    fn decide<R: Redis>(&self,
+    fn decide<B: Backend>(&self,
                         agent: &Agent<Self::State>,
                         world: &Self::World,
-                        population: &Population<Self, R>,
+                        population: &Population<Self, B>,
                         updates: &mut Updates<Self>)
                         -> () {
         updates.queue(agent.id, Update::ChangeHealth(HEALTH_CHANGE));
@@ -71,11 +73,11 @@ fn main() {
     // Setup the manager
     let addr = "redis://127.0.0.1/";
     let client = Client::open(addr).unwrap();
-    let mut manager = Manager::new(addr, client, sim.clone());
+    let mut manager = Manager::new(client, sim.clone()).unwrap();
 
     // Spawn the population
-    manager.spawn(State { health: 0 });
-    let id = manager.spawn(State { health: HEALTH_START });
+    manager.spawn(State { health: 0 }).unwrap();
+    let id = manager.spawn(State { health: HEALTH_START }).unwrap();
 
     // Create a websocket server to pass messages to frontend clients
     let mut ws = WebSocketServer::new("127.0.0.1:3012", addr);
@@ -100,16 +102,17 @@ fn main() {
 
     // Register a really simple reporter
     manager.register_reporter(1, |step, pop, conn| {
-        let world = pop.world();
-        let _: () = conn.publish("weather", world.weather.clone()).unwrap();
-        let _: () = conn.publish("ws", world.weather.clone()).unwrap();
+        let world = pop.world().unwrap();
+        conn.publish("weather", &world.weather).unwrap();
+        conn.publish("ws", &world.weather).unwrap();
     });
 
-    manager = run(sim, world, manager, 4, n_steps);
+    let (manager, summary) = run(sim, world, manager, 4, n_steps).unwrap();
+    println!("Stopped at step {}: {:?}", summary.step, summary.reason);
     log_t.join().unwrap();
 
     // Check that things are working
-    let agent = match manager.population.get_agent(id) {
+    let agent = match manager.population.get_agent(id).unwrap() {
         Some(a) => a,
         None => panic!("Couldn't find the agent"),
     };
@@ -1,6 +1,7 @@
 extern crate rand;
 extern crate djinn;
 
+use djinn::seeded_rng;
 use djinn::ext::bnet::{Var, Dataset, BNet};
 use std::collections::HashMap;
 use rand::distributions::{IndependentSample, Range};
@@ -29,7 +30,8 @@ struct Datum {
 fn main() {
     // generate a dataset
     let n_samples = 100000;
-    let mut rng = rand::thread_rng();
+    // seeded, so the dataset is the same from run to run
+    let mut rng = seeded_rng(0);
     let ages = Range::new(1, 100);
     let low_income = Range::new(0, 20000);
     let mid_income = Range::new(10000, 30000);
@@ -98,8 +100,7 @@ fn main() {
             _ => 4,
         }
     });
-    graph.register_sampler(Vars::Age, |i| {
-        let mut rng = rand::thread_rng();
+    graph.register_sampler(Vars::Age, |i, mut rng| {
         let ages = if i <= 14 {
             Range::new(1, 14)
         } else if i <= 24 {
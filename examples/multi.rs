@@ -7,31 +7,32 @@
 extern crate djinn;
 extern crate redis;
 extern crate redis_cluster;
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
 
 use redis::Client;
-use djinn::{Agent, Manager, Simulation, Population, Updates, Redis, run};
+use djinn::{Agent, Manager, Simulation, Population, Updates, Backend, run, MessagePack};
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct Cat {
     purrs: usize,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct Person {
     health: isize,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 enum State {
     Person(Person),
     Cat(Cat),
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct World {}
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 enum Update {
     ChangeHealth(isize),
     Purr,
@@ -44,11 +45,12 @@ impl Simulation for MultiSim {
     type State = State;
     type Update = Update;
     type World = World;
+    type Codec = MessagePack;
 
-    fn decide<R: Redis>(&self,
+    fn decide<B: Backend>(&self,
                         agent: &Agent<Self::State>,
                         world: &Self::World,
-                        population: &Population<Self, R>,
+                        population: &Population<Self, B>,
                         updates: &mut Updates<Self>)
                         -> () {
         match agent.state {
@@ -92,10 +94,10 @@ fn main() {
     // Setup the manager
     let addr = "redis://127.0.0.1/";
     let pop_client = Client::open(addr).unwrap();
-    let mut manager = Manager::new(addr, pop_client, sim.clone());
+    let mut manager = Manager::new(pop_client, sim.clone()).unwrap();
 
     // Spawn the population
-    manager.spawns(vec![State::Person(Person { health: 100 }), State::Cat(Cat { purrs: 0 })]);
+    manager.spawns(vec![State::Person(Person { health: 100 }), State::Cat(Cat { purrs: 0 })]).unwrap();
 
-    manager = run(sim, world, manager, 4, 10);
+    run(sim, world, manager, 4, 10).unwrap();
 }
@@ -11,13 +11,13 @@ const MAX_POS_TRUST_SHIFT: f64 = 1.;
 // their trust improves
 const MAX_POS_TRUST_SHIFT_RANGE: f64 = 4.;
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Opinion {
     pub polarity: i32,
     pub priority: u32,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Person {
     pub opinions: Vec<Opinion>,
     pub medias: HashMap<u64, u32>,
@@ -58,6 +58,9 @@ impl Person {
                 }
             })
             .collect();
+
+        // sort so the same draw picks the same edge from run to run
+        items.sort_by_key(|w| w.item);
         let wc = WeightedChoice::new(&mut items);
         wc.ind_sample(&mut rng)
     }
@@ -107,7 +110,7 @@ impl Person {
     }
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Media {
     pub opinions: Vec<Opinion>,
 }
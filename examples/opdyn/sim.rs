@@ -1,24 +1,23 @@
-use rand;
 use rand::Rng;
 use super::ent::{Person, Media};
-use djinn::{Agent, Simulation, Population, Updates, Redis};
+use djinn::{Agent, Simulation, Population, Updates, Backend, MessagePack};
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct World {}
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum EdgeType {
     Media,
     Friend,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum State {
     Person(Person),
     Media(Media),
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum PersonUpdate {
     OpinionShift { idx: usize, polarity: i32 },
     TrustShift {
@@ -28,12 +27,12 @@ pub enum PersonUpdate {
     },
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum MediaUpdate {
     Click { idx: usize, polarity: i32 },
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum Update {
     Person(PersonUpdate),
     Media(MediaUpdate),
@@ -47,49 +46,49 @@ pub struct OpinionDynamicsSim {
 }
 
 impl OpinionDynamicsSim {
-    fn decide_media<R: Redis>(&self,
+    fn decide_media<B: Backend>(&self,
                               id: u64,
                               media: &Media,
-                              pop: &Population<Self, R>,
+                              pop: &Population<Self, B>,
                               updates: &mut Updates<Self>)
                               -> () {
-        // media's not really doing anything atm
+        // media's not really doing anything atm,
+        // so sleep until someone clicks on a story
+        updates.sleep();
     }
 
-    fn decide_person<R: Redis>(&self,
+    fn decide_person<B: Backend>(&self,
                                id: u64,
                                person: &Person,
-                               pop: &Population<Self, R>,
+                               pop: &Population<Self, B>,
                                updates: &mut Updates<Self>)
                                -> () {
-        let mut rng = rand::weak_rng();
-
         // talk to a person or consume media?
-        let other = if rng.gen::<f64>() < 0.5 {
+        let other = if updates.rng().gen::<f64>() < 0.5 {
             // choose a media.
             // the less media this person is familiar with,
             // the more likely they will encounter a random one.
             // otherwise, they choose one with probability based on how much they trust it.
             let p_rand_media = 1. - ((person.medias.len() as f64) / 2.); // TODO denom should be a config val
-            if rng.gen::<f64>() < p_rand_media {
-                pop.random("media")
+            if updates.rng().gen::<f64>() < p_rand_media {
+                pop.random_with("media", updates.rng()).unwrap().unwrap()
             } else {
-                let id = person.rand_edge(&mut rng, &person.medias);
-                pop.get_agent(id).unwrap()
+                let id = person.rand_edge(updates.rng(), &person.medias);
+                pop.get_agent(id).unwrap().unwrap()
             }
         } else {
             // choose a person to talk to.
             let p_rand_person = 1. - ((person.friends.len() as f64) / 2.); // TODO denom should be a config val
-            if rng.gen::<f64>() < p_rand_person {
-                pop.random("people") // TODO prob shouldnt be themselves
+            if updates.rng().gen::<f64>() < p_rand_person {
+                pop.random_with("people", updates.rng()).unwrap().unwrap() // TODO prob shouldnt be themselves
             } else {
-                let id = person.rand_edge(&mut rng, &person.friends);
-                pop.get_agent(id).unwrap()
+                let id = person.rand_edge(updates.rng(), &person.friends);
+                pop.get_agent(id).unwrap().unwrap()
             }
         };
         match other.state {
             State::Person(ref p) => {
-                let op_idx = person.rand_opinion_idx(&mut rng, &p.opinions);
+                let op_idx = person.rand_opinion_idx(updates.rng(), &p.opinions);
                 let ref op1 = person.opinions[op_idx];
                 let ref op2 = p.opinions[op_idx];
 
@@ -99,7 +98,7 @@ impl OpinionDynamicsSim {
                     None => 0,
                 };
                 let p_opinion_shift = ((trust as f64) + 0.01) / 100.;
-                if rng.gen::<f64>() < p_opinion_shift {
+                if updates.rng().gen::<f64>() < p_opinion_shift {
                     updates.queue(id,
                                   Update::Person(person.be_influenced(op_idx,
                                                      op1,
@@ -115,7 +114,7 @@ impl OpinionDynamicsSim {
                               }))
             }
             State::Media(ref m) => {
-                let op_idx = person.rand_opinion_idx(&mut rng, &m.opinions);
+                let op_idx = person.rand_opinion_idx(updates.rng(), &m.opinions);
                 let ref op1 = person.opinions[op_idx];
                 let ref op2 = m.opinions[op_idx];
 
@@ -133,7 +132,7 @@ impl OpinionDynamicsSim {
                 };
 
                 let p_opinion_shift = ((trust as f64) + 0.01) / 100.;
-                if rng.gen::<f64>() < p_opinion_shift {
+                if updates.rng().gen::<f64>() < p_opinion_shift {
                     updates.queue(id,
                                   Update::Person(person.be_influenced(op_idx,
                                                      op1,
@@ -210,11 +209,12 @@ impl Simulation for OpinionDynamicsSim {
     type State = State;
     type Update = Update;
     type World = World;
+    type Codec = MessagePack;
 
-    fn decide<R: Redis>(&self,
+    fn decide<B: Backend>(&self,
                         agent: &Agent<Self::State>,
                         world: &Self::World,
-                        pop: &Population<Self, R>,
+                        pop: &Population<Self, B>,
                         updates: &mut Updates<Self>)
                         -> () {
         match agent.state {
@@ -234,9 +234,9 @@ impl Simulation for OpinionDynamicsSim {
         }
     }
 
-    fn on_spawns<R: Redis>(&self,
+    fn on_spawns<B: Backend>(&self,
                            agents: Vec<Agent<Self::State>>,
-                           population: &Population<Self, R>)
+                           population: &Population<Self, B>)
                            -> () {
         // index newly created media/people
         // so we can sample them later
@@ -250,7 +250,7 @@ impl Simulation for OpinionDynamicsSim {
                 }
             })
             .collect();
-        let _: () = population.indexes("media", media);
-        let _: () = population.indexes("people", people);
+        population.indexes("media", media).unwrap();
+        population.indexes("people", people).unwrap();
     }
 }
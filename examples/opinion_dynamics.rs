@@ -1,7 +1,8 @@
 extern crate rand;
 extern crate djinn;
 extern crate redis;
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
 
 mod opdyn;
 
@@ -12,7 +13,7 @@ use opdyn::{OpinionDynamicsSim, State, World, Person, Media, Opinion};
 
 
 fn main() {
-    let conf = load_from_yaml("examples/conf/opdyn.yaml");
+    let conf = load_from_yaml("examples/conf/opdyn.yaml").unwrap();
     let sim = OpinionDynamicsSim {
         opinion_shift_proportion: conf["opinion_shift_proportion"].as_f64().unwrap(),
     };
@@ -21,7 +22,7 @@ fn main() {
     // Setup the manager
     let addr = "redis://127.0.0.1/";
     let pop_client = Client::open(addr).unwrap();
-    let mut manager = Manager::new(addr, pop_client, sim.clone());
+    let mut manager = Manager::new(pop_client, sim.clone()).unwrap();
 
     let mut medias = vec![Media {
                               opinions: vec![Opinion {
@@ -43,7 +44,7 @@ fn main() {
                                                  priority: 4,
                                              }],
                           }];
-    let media_ids = manager.spawns(medias.drain(..).map(|m| State::Media(m)).collect());
+    let media_ids = manager.spawns(medias.drain(..).map(|m| State::Media(m)).collect()).unwrap();
 
     let mut people = vec![Person::new(vec![Opinion {
                                                polarity: 100,
@@ -61,11 +62,11 @@ fn main() {
                                                polarity: 100,
                                                priority: 0,
                                            }])];
-    let people_ids = manager.spawns(people.drain(..).map(|m| State::Person(m)).collect());
+    let people_ids = manager.spawns(people.drain(..).map(|m| State::Person(m)).collect()).unwrap();
 
     manager.register_reporter(1, |step, pop, _| {
         // Mean polarity of first issue
-        let people: Vec<Agent<State>> = pop.lookup("people");
+        let people: Vec<Agent<State>> = pop.lookup("people").unwrap();
         let polarity = people.iter().fold(0, |acc, a| {
             match a.state {
                 State::Person(ref p) => acc + p.opinions[0].polarity,
@@ -78,5 +79,5 @@ fn main() {
     });
 
     println!("running");
-    run(sim, world, manager, 4, 10);
+    run(sim, world, manager, 4, 10).unwrap();
 }
@@ -2,8 +2,9 @@
 //! Refer to: <https://www.openabm.org/book/33102/6-population-dynamics>
 //!
 //! Demonstrates:
-//! - toroidal space
+//! - toroidal space, using a `Grid`
 //! - agents that are born and die
+//! - partitioning agents across workers by region
 //!
 //! General model:
 //! - space
@@ -21,63 +22,33 @@ extern crate rand;
 extern crate djinn;
 extern crate redis;
 extern crate redis_cluster;
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
 
-use std::cmp;
+use std::sync::Arc;
 use rand::Rng;
 use redis::Client;
-use std::collections::{HashMap, HashSet};
-use djinn::{Agent, Manager, Simulation, Population, Redis, Updates, run};
+use djinn::{Agent, Manager, Simulation, Population, Backend, Updates, run, Bincode, seeded_rng};
+use djinn::{Grid, Pos, Topology, Neighborhood, Partitioner, RegionPartitioner};
 
-#[derive(RustcDecodable, RustcEncodable, Debug, Eq, PartialEq, Hash, Clone, Copy)]
-pub struct Pos {
-    x: usize,
-    y: usize,
-}
-
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Cell {
-    pos: Pos,
     resources: usize,
-    occupants: HashSet<u64>,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct State {
     resources: usize,
     pos: Pos,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
-pub struct World {
-    cells: HashMap<Pos, Cell>,
-}
-
-impl World {
-    pub fn new(width: usize, height: usize, resource_per_cell: usize) -> World {
-        let mut cells = HashMap::new();
-        for x in 0..width {
-            for y in 0..height {
-                let pos = Pos { x: x, y: y };
-                cells.insert(pos,
-                             Cell {
-                                 pos: pos,
-                                 resources: resource_per_cell,
-                                 occupants: HashSet::new(),
-                             });
-            }
-        }
-        World { cells: cells }
-    }
-}
+// the space lives in the grid rather than the world
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct World {}
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum Update {
     GiveResource(usize),
-    Replenish(Vec<Pos>),
-    Drain(Vec<Pos>),
-    NewOccupant(u64, Pos),
-    MoveOccupant(u64, Pos, Pos),
     MoveTo(Pos),
 }
 
@@ -88,75 +59,66 @@ pub struct Sim {
     resource_per_cell: usize,
     resource_to_live: usize,
     start_resources: usize,
-    width: usize,
-    height: usize,
+    grid: Grid<Cell>,
 }
 
 impl Simulation for Sim {
     type State = State;
     type Update = Update;
     type World = World;
+    type Codec = Bincode;
+
+    // give each worker a block of the grid
+    fn partitioner(&self) -> Arc<Partitioner> {
+        Arc::new(RegionPartitioner::for_grid(&self.grid))
+    }
 
-    fn on_spawns<R: Redis>(&self,
+    fn on_spawns<B: Backend>(&self,
                            agents: Vec<Agent<Self::State>>,
-                           population: &Population<Self, R>)
+                           population: &Population<Self, B>)
                            -> () {
         let people = agents.iter().map(|agent| agent.id).collect();
-        let _: () = population.indexes("people", people);
+        population.indexes("people", people).unwrap();
     }
 
-    fn on_deaths<R: Redis>(&self,
+    fn on_deaths<B: Backend>(&self,
                            agents: Vec<Agent<Self::State>>,
-                           population: &Population<Self, R>)
+                           population: &Population<Self, B>)
                            -> () {
         let people = agents.iter().map(|agent| agent.id).collect();
-        let _: () = population.unindexes("people", people);
+        population.unindexes("people", people).unwrap();
     }
 
-    fn decide<R: Redis>(&self,
+    fn decide<B: Backend>(&self,
                         agent: &Agent<Self::State>,
                         world: &Self::World,
-                        pop: &Population<Self, R>,
+                        pop: &Population<Self, B>,
                         updates: &mut Updates<Self>)
                         -> () {
         let s = agent.state.clone();
         if s.resources <= 0 {
             // died
             updates.kill(agent);
+            self.grid.queue_remove(updates, agent.id, s.pos);
         } else {
             if s.resources >= self.birth_threshold {
-                // birthed
-                let id = updates.spawn(State {
-                    resources: self.start_resources,
-                    pos: s.pos,
-                });
-                updates.queue_world(Update::NewOccupant(id, s.pos));
+                // birthed, on the same worker as other agents in this region
+                let id = updates.spawn_placed(State {
+                                                  resources: self.start_resources,
+                                                  pos: s.pos,
+                                              },
+                                              self.grid.placement(s.pos));
+                self.grid.queue_place(updates, id, s.pos).unwrap();
             }
 
-            let c = world.cells.get(&s.pos).unwrap();
+            let c = self.grid.cell(pop, s.pos).unwrap().unwrap();
             // move
             if c.resources <= 0 {
                 // random adjacent cell
-                let mut rng = rand::weak_rng();
-
-                let x: isize = if rng.gen::<f64>() < 0.5 {
-                    cmp::min(s.pos.x + 1, self.width - 1) as isize
-                } else {
-                    cmp::max((s.pos.x as isize) - 1, 0)
-                };
-
-                let y: isize = if rng.gen::<f64>() < 0.5 {
-                    cmp::min(s.pos.y + 1, self.height - 1) as isize
-                } else {
-                    cmp::max((s.pos.y as isize) - 1, 0)
-                };
-
-                let pos = Pos {
-                    x: (x as usize),
-                    y: (y as usize),
-                };
+                let neighborhood = self.grid.neighborhood(s.pos, Neighborhood::Moore, 1);
+                let pos = *updates.rng().choose(&neighborhood).unwrap();
                 updates.queue(agent.id, Update::MoveTo(pos));
-                updates.queue_world(Update::MoveOccupant(agent.id, s.pos, pos));
+                self.grid.queue_move(updates, agent.id, s.pos, pos).unwrap();
             }
         }
     }
@@ -175,7 +137,6 @@ impl Simulation for Sim {
                     state.resources += amt;
                     changed = true;
                 }
-                _ => (),
             }
         }
         // metabolize
@@ -187,59 +148,37 @@ impl Simulation for Sim {
         changed || state.resources != old_resources
     }
 
-    fn world_decide<R: Redis>(&self,
+    fn world_decide<B: Backend>(&self,
                               world: &Self::World,
-                              population: &Population<Self, R>,
+                              population: &Population<Self, B>,
                               updates: &mut Updates<Self>)
                               -> () {
-        let mut to_drain = Vec::new();
-        let mut to_replenish = Vec::new();
-        let mut rng = rand::weak_rng();
-        let _: Vec<()> = world.cells
-            .iter()
-            .map(|(pos, c)| {
-                if c.resources > 0 {
-                    let ids = rand::sample(&mut rng, c.occupants.clone(), 1);
-                    if ids.len() > 0 {
-                        updates.queue(ids[0], Update::GiveResource(c.resources));
-                        to_drain.push(*pos);
-                    }
-                } else if rng.gen::<f64>() <= self.p_replenishment {
-                    to_replenish.push(*pos);
-                }
-            })
-            .collect();
-        updates.queue_world(Update::Replenish(to_replenish));
-        updates.queue_world(Update::Drain(to_drain));
-
-    }
-
-    fn world_update(&self, mut world: Self::World, updates: Vec<Self::Update>) -> Self::World {
-        for update in updates {
-            match update {
-                // assuming each agent makes only one move per step
-                Update::Replenish(to_replenish) => {
-                    for pos in to_replenish {
-                        let c = world.cells.get_mut(&pos).unwrap();
-                        c.resources = self.resource_per_cell;
-                    }
-                }
-                Update::Drain(to_drain) => {
-                    for pos in to_drain {
-                        let c = world.cells.get_mut(&pos).unwrap();
-                        c.resources = 0;
-                    }
-                }
-                Update::NewOccupant(id, pos) => {
-                    world.cells.get_mut(&pos).unwrap().occupants.insert(id);
-                }
-                Update::MoveOccupant(id, pos_a, pos_b) => {
-                    world.cells.get_mut(&pos_a).unwrap().occupants.remove(&id);
-                    world.cells.get_mut(&pos_b).unwrap().occupants.insert(id);
+        // go through cells and occupants in order,
+        // so random draws are the same from run to run
+        let mut positions = Vec::new();
+        for y in 0..self.grid.height {
+            for x in 0..self.grid.width {
+                positions.push(Pos { x: x, y: y });
+            }
+        }
+        let cells = self.grid.cells(population, &positions).unwrap();
+        for (pos, c) in positions.into_iter().zip(cells) {
+            let c = c.unwrap();
+            if c.resources > 0 {
+                let occupants = self.grid.occupants(population, pos).unwrap();
+                let ids = rand::sample(updates.rng(), occupants, 1);
+                if ids.len() > 0 {
+                    updates.queue(ids[0], Update::GiveResource(c.resources));
+                    self.grid.queue_set_cell(updates, pos, &Cell { resources: 0 }).unwrap();
                 }
-                _ => (),
+            } else if updates.rng().gen::<f64>() <= self.p_replenishment {
+                let cell = Cell { resources: self.resource_per_cell };
+                self.grid.queue_set_cell(updates, pos, &cell).unwrap();
             }
         }
+    }
+
+    fn world_update(&self, world: Self::World, updates: Vec<Self::Update>) -> Self::World {
         world
     }
 }
@@ -252,35 +191,49 @@ fn main() {
         resource_per_cell: 6,
         resource_to_live: 6,
         start_resources: 10,
-        height: 20,
-        width: 20,
+        grid: Grid::new("space", 20, 20, Topology::Toroidal),
     };
 
     let addr = "redis://127.0.0.1/";
-    let mut world = World::new(sim.width, sim.height, sim.resource_per_cell);
+    let world = World {};
     let pop_client = Client::open(addr).unwrap();
-    let positions: Vec<Pos> = world.cells.keys().cloned().collect();
-    let mut manager = Manager::new(addr, pop_client, sim.clone());
+    let mut manager = Manager::new(pop_client, sim.clone()).unwrap();
 
     println!("setting up");
+    let mut positions = Vec::new();
+    for y in 0..sim.grid.height {
+        for x in 0..sim.grid.width {
+            let pos = Pos { x: x, y: y };
+            let cell = Cell { resources: sim.resource_per_cell };
+            sim.grid.set_cell(&manager.population, pos, &cell).unwrap();
+            positions.push(pos);
+        }
+    }
+
     let start_pop_size = 10000;
-    let mut rng = rand::thread_rng();
+    // place agents reproducibly, from the run's seed
+    let mut rng = seeded_rng(manager.seed());
     for _ in 0..start_pop_size {
-        let poss = rand::sample(&mut rng, positions.clone(), 1);
-        let pos = poss[0];
-        let id = manager.spawn(State {
-            resources: sim.start_resources,
-            pos: pos,
-        });
-        world.cells.get_mut(&pos).unwrap().occupants.insert(id);
+        let pos = *rng.choose(&positions).unwrap();
+        let id = manager.spawn_placed(State {
+                                          resources: sim.start_resources,
+                                          pos: pos,
+                                      },
+                                      sim.grid.placement(pos))
+            .unwrap();
+        sim.grid.place(&manager.population, id, pos).unwrap();
     }
 
     // Register a really simple reporter
     manager.register_reporter(1, |step, pop, conn| {
-        let popsize: usize = pop.count_index("people");
+        let popsize: usize = pop.count_index("people").unwrap();
         println!("[{:02}] population: {}", step, popsize);
     });
 
+    // Don't keep stepping an empty world
+    manager.stop_on_extinction();
+
     println!("running");
-    run(sim, world, manager, 4, 10);
+    let (_, summary) = run(sim, world, manager, 4, 10).unwrap();
+    println!("Stopped at step {}: {:?}", summary.step, summary.reason);
 }
@@ -88,7 +88,7 @@ fn main() {
     let mut explorer = Explorer {
         state: State { x: 0, y: 0 },
         env: env,
-        qlp: QLearnerParams::new(0.5, 0.5, 0.5),
+        qlp: QLearnerParams::new(0.5, 0.5, 0.5, 0),
     };
 
     let n_episodes = 100;
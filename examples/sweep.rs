@@ -0,0 +1,96 @@
+//! # Parameter sweep
+//! Runs a simple simulation across a grid of parameters
+//! (see `examples/conf/sweep.yaml`), with several replicates each,
+//! and saves the mean wealth at each step to a CSV file.
+
+extern crate djinn;
+extern crate rand;
+#[macro_use]
+extern crate serde_derive;
+
+use rand::Rng;
+use djinn::{Agent, Simulation, Population, Updates, Backend, MemoryBackend, MessagePack};
+use djinn::ext::yaml::load_from_yaml;
+use djinn::ext::sweep::Sweep;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct State {
+    wealth: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct World {}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Update {
+    Gain,
+}
+
+#[derive(Clone)]
+pub struct WealthSim {
+    p_gain: f64,
+}
+
+impl Simulation for WealthSim {
+    type State = State;
+    type Update = Update;
+    type World = World;
+    type Codec = MessagePack;
+
+    fn on_spawns<B: Backend>(&self,
+                           agents: Vec<Agent<Self::State>>,
+                           population: &Population<Self, B>)
+                           -> () {
+        let ids = agents.iter().map(|a| a.id).collect();
+        population.indexes("people", ids).unwrap();
+    }
+
+    fn decide<B: Backend>(&self,
+                        agent: &Agent<Self::State>,
+                        world: &Self::World,
+                        population: &Population<Self, B>,
+                        updates: &mut Updates<Self>)
+                        -> () {
+        if updates.rng().gen::<f64>() < self.p_gain {
+            updates.queue(agent.id, Update::Gain);
+        }
+    }
+
+    fn update(&self, mut state: &mut Self::State, updates: Vec<Self::Update>) -> bool {
+        let old_wealth = state.wealth;
+        for update in updates {
+            match update {
+                Update::Gain => state.wealth += 1,
+            }
+        }
+        state.wealth != old_wealth
+    }
+}
+
+fn main() {
+    let conf = load_from_yaml("examples/conf/sweep.yaml").unwrap();
+
+    let mut sweep = Sweep::new(0,
+                               |params| WealthSim { p_gain: params["p_gain"].as_f64().unwrap() },
+                               |params, manager| {
+        let n_people = params["n_people"].as_i64().unwrap() as usize;
+        let people = (0..n_people).map(|_| State { wealth: 0 }).collect();
+        manager.spawns(people)?;
+        Ok(World {})
+    });
+    sweep.load_yaml(&conf).unwrap();
+
+    sweep.register_recorder(1, |_, pop| {
+        let people = pop.lookup("people")?;
+        let total: usize = people.iter().map(|a| a.state.wealth).sum();
+        Ok(vec![("mean_wealth".to_owned(), (total as f64 / people.len() as f64).into())])
+    });
+
+    // run two configurations at a time
+    let pools = vec![MemoryBackend::new(), MemoryBackend::new()];
+    let results = sweep.run(pools).unwrap();
+    for &(ref run, ref summary) in &results.runs {
+        println!("{:?} #{}: stopped at step {}", run.params, run.replicate, summary.step);
+    }
+    results.save_csv("sweep.csv").unwrap();
+}
@@ -0,0 +1,654 @@
+use rand;
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::cmp;
+use std::collections::VecDeque;
+use redis::{Client, Commands, PubSub};
+use fnv::{FnvHashMap, FnvHashSet};
+use error::Result;
+
+/// Picks random members of a set and gets the values at the keys they name,
+/// for `srandmember_values`.
+const SRANDMEMBER_VALUES_SCRIPT: &'static str = "
+local ids = redis.call('SRANDMEMBER', KEYS[1], ARGV[1])
+local res = {}
+for _, id in ipairs(ids) do
+    table.insert(res, id)
+    table.insert(res, redis.call('GET', id))
+end
+return res";
+
+/// A write to the backend, e.g. one queued during `decide`
+/// and applied in the update phase (like moving an agent on a `Grid`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendOp {
+    /// Set a value.
+    Set(String, Vec<u8>),
+
+    /// Delete a key.
+    Del(String),
+
+    /// Add a member to a set.
+    Add(String, Vec<u8>),
+
+    /// Remove a member from a set.
+    Remove(String, Vec<u8>),
+
+    /// Push values onto the head of a list.
+    Push(String, Vec<Vec<u8>>),
+}
+
+/// A storage and coordination backend for a simulation.
+///
+/// This covers the subset of Redis that djinn relies on:
+/// key/value pairs, sets, lists, and pubsub channels for commanding workers.
+/// Values and members are stored as raw bytes.
+pub trait Backend: Send + Sync + Clone {
+    type Subscription: Subscription;
+
+    /// Get the value at a key, if any.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Get the values at multiple keys, in one request.
+    fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>>;
+
+    /// Set the value at a key.
+    fn set(&self, key: &str, value: Vec<u8>) -> Result<()>;
+
+    /// Set the values at multiple keys, in one request.
+    fn set_multiple(&self, items: &[(String, Vec<u8>)]) -> Result<()>;
+
+    /// Delete a key, whatever type of value it holds.
+    fn del(&self, key: &str) -> Result<()>;
+
+    /// Delete multiple keys.
+    fn del_multiple(&self, keys: &[String]) -> Result<()>;
+
+    /// List keys matching a glob-style pattern, e.g. `idx:*`.
+    fn keys(&self, pattern: &str) -> Result<Vec<String>>;
+
+    /// Add members to a set.
+    fn sadd(&self, key: &str, members: Vec<Vec<u8>>) -> Result<()>;
+
+    /// Remove members from a set.
+    fn srem(&self, key: &str, members: Vec<Vec<u8>>) -> Result<()>;
+
+    /// Get all members of a set.
+    fn smembers(&self, key: &str) -> Result<Vec<Vec<u8>>>;
+
+    /// Get all members of multiple sets, in one request.
+    fn smembers_multiple(&self, keys: &[String]) -> Result<Vec<Vec<Vec<u8>>>>;
+
+    /// Get all members of a set, each with the value at the key the member names
+    /// (e.g. an index of agent ids, with their states), in one request.
+    fn smembers_values(&self, key: &str) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>>;
+
+    /// Count the members of a set.
+    fn scard(&self, key: &str) -> Result<usize>;
+
+    /// Get up to `count` random, distinct members of a set.
+    fn srandmember(&self, key: &str, count: usize) -> Result<Vec<Vec<u8>>>;
+
+    /// Get up to `count` random, distinct members of a set,
+    /// each with the value at the key the member names, in one request.
+    fn srandmember_values(&self, key: &str, count: usize) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>>;
+
+    /// Incrementally iterate over the members of a set, roughly `count` at a time.
+    /// Start with a cursor of 0; iteration is done when the returned cursor is 0 again.
+    fn sscan(&self, key: &str, cursor: u64, count: usize) -> Result<(u64, Vec<Vec<u8>>)>;
+
+    /// Push values onto the head of a list.
+    fn lpush(&self, key: &str, values: Vec<Vec<u8>>) -> Result<()>;
+
+    /// Pop a value from the head of a list, if any.
+    fn lpop(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Get all values of a list, head first.
+    fn lrange(&self, key: &str) -> Result<Vec<Vec<u8>>>;
+
+    /// Get all values of multiple lists, head first, and delete the lists, in one request.
+    fn take_lists(&self, keys: &[String]) -> Result<Vec<Vec<Vec<u8>>>>;
+
+    /// Pop a value from the head of the first of some lists that isn't empty,
+    /// along with its key, blocking until there is one or the timeout is up.
+    /// Redis only blocks for whole seconds, so the timeout is rounded up to at least one.
+    fn blpop(&self, keys: &[String], timeout: Duration) -> Result<Option<(String, Vec<u8>)>>;
+
+    /// Apply writes in order, in one request.
+    fn apply(&self, ops: Vec<BackendOp>) -> Result<()>;
+
+    /// Publish a message to a channel.
+    fn publish(&self, channel: &str, msg: &str) -> Result<()>;
+
+    /// Subscribe to a channel.
+    /// Only messages published after subscribing are received.
+    fn subscribe(&self, channel: &str) -> Result<Self::Subscription>;
+}
+
+/// A subscription to a backend channel.
+pub trait Subscription {
+    /// Block until the next message arrives.
+    fn next_message(&mut self) -> Result<String>;
+}
+
+impl Backend for Client {
+    type Subscription = PubSub;
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(Commands::get(self, key)?)
+    }
+
+    fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(::redis::cmd("MGET").arg(keys).query(self)?)
+    }
+
+    fn set(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        Ok(Commands::set(self, key, value)?)
+    }
+
+    fn set_multiple(&self, items: &[(String, Vec<u8>)]) -> Result<()> {
+        if !items.is_empty() {
+            let _: () = Commands::set_multiple(self, items)?;
+        }
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> Result<()> {
+        Ok(Commands::del(self, key)?)
+    }
+
+    fn del_multiple(&self, keys: &[String]) -> Result<()> {
+        if !keys.is_empty() {
+            let _: () = Commands::del(self, keys)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+        Ok(Commands::keys(self, pattern)?)
+    }
+
+    fn sadd(&self, key: &str, members: Vec<Vec<u8>>) -> Result<()> {
+        if !members.is_empty() {
+            let _: () = Commands::sadd(self, key, members)?;
+        }
+        Ok(())
+    }
+
+    fn srem(&self, key: &str, members: Vec<Vec<u8>>) -> Result<()> {
+        if !members.is_empty() {
+            let _: () = Commands::srem(self, key, members)?;
+        }
+        Ok(())
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+        Ok(Commands::smembers(self, key)?)
+    }
+
+    fn smembers_multiple(&self, keys: &[String]) -> Result<Vec<Vec<Vec<u8>>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipe = ::redis::pipe();
+        for key in keys {
+            pipe.cmd("SMEMBERS").arg(key);
+        }
+        Ok(pipe.query(self)?)
+    }
+
+    fn smembers_values(&self, key: &str) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let flat: Vec<Option<Vec<u8>>> = ::redis::cmd("SORT")
+            .arg(key)
+            .arg("BY")
+            .arg("nosort")
+            .arg("GET")
+            .arg("#")
+            .arg("GET")
+            .arg("*")
+            .query(self)?;
+        Ok(pairs(flat))
+    }
+
+    fn scard(&self, key: &str) -> Result<usize> {
+        Ok(Commands::scard(self, key)?)
+    }
+
+    fn srandmember(&self, key: &str, count: usize) -> Result<Vec<Vec<u8>>> {
+        Ok(Commands::srandmember_multiple(self, key, count)?)
+    }
+
+    fn srandmember_values(&self, key: &str, count: usize) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let flat: Vec<Option<Vec<u8>>> = ::redis::cmd("EVAL")
+            .arg(SRANDMEMBER_VALUES_SCRIPT)
+            .arg(1usize)
+            .arg(key)
+            .arg(count)
+            .query(self)?;
+        Ok(pairs(flat))
+    }
+
+    fn sscan(&self, key: &str, cursor: u64, count: usize) -> Result<(u64, Vec<Vec<u8>>)> {
+        Ok(::redis::cmd("SSCAN").arg(key).arg(cursor).arg("COUNT").arg(count).query(self)?)
+    }
+
+    fn lpush(&self, key: &str, values: Vec<Vec<u8>>) -> Result<()> {
+        if !values.is_empty() {
+            let _: () = Commands::lpush(self, key, values)?;
+        }
+        Ok(())
+    }
+
+    fn lpop(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(Commands::lpop(self, key)?)
+    }
+
+    fn lrange(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+        Ok(Commands::lrange(self, key, 0, -1)?)
+    }
+
+    fn take_lists(&self, keys: &[String]) -> Result<Vec<Vec<Vec<u8>>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // atomic, so nothing pushed between reading and deleting a list is lost
+        let mut pipe = ::redis::pipe();
+        pipe.atomic();
+        for key in keys {
+            pipe.cmd("LRANGE").arg(key).arg(0isize).arg(-1isize).cmd("DEL").arg(key).ignore();
+        }
+        Ok(pipe.query(self)?)
+    }
+
+    fn blpop(&self, keys: &[String], timeout: Duration) -> Result<Option<(String, Vec<u8>)>> {
+        let secs = timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 };
+        Ok(::redis::cmd("BLPOP").arg(keys).arg(cmp::max(secs, 1)).query(self)?)
+    }
+
+    fn apply(&self, ops: Vec<BackendOp>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = ::redis::pipe();
+        for op in ops {
+            match op {
+                BackendOp::Set(key, val) => pipe.cmd("SET").arg(key).arg(val).ignore(),
+                BackendOp::Del(key) => pipe.cmd("DEL").arg(key).ignore(),
+                BackendOp::Add(key, val) => pipe.cmd("SADD").arg(key).arg(val).ignore(),
+                BackendOp::Remove(key, val) => pipe.cmd("SREM").arg(key).arg(val).ignore(),
+                BackendOp::Push(key, vals) => {
+                    if vals.is_empty() {
+                        continue;
+                    }
+                    pipe.cmd("LPUSH").arg(key).arg(vals).ignore()
+                }
+            };
+        }
+        let _: () = pipe.query(self)?;
+        Ok(())
+    }
+
+    fn publish(&self, channel: &str, msg: &str) -> Result<()> {
+        Ok(Commands::publish(self, channel, msg)?)
+    }
+
+    fn subscribe(&self, channel: &str) -> Result<PubSub> {
+        let mut pubsub = self.get_pubsub()?;
+        pubsub.subscribe(channel)?;
+        Ok(pubsub)
+    }
+}
+
+impl Subscription for PubSub {
+    fn next_message(&mut self) -> Result<String> {
+        let msg = self.get_message()?;
+        Ok(msg.get_payload()?)
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    values: FnvHashMap<String, Vec<u8>>,
+    sets: FnvHashMap<String, FnvHashSet<Vec<u8>>>,
+    lists: FnvHashMap<String, VecDeque<Vec<u8>>>,
+    channels: FnvHashMap<String, Vec<Sender<String>>>,
+}
+
+/// An in-process backend, for running simulations without a Redis server.
+///
+/// Clones share the same underlying store, so a manager and
+/// its workers can all be run from one process, e.g. with `run`.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    store: Arc<Mutex<Store>>,
+
+    /// Signalled whenever values are pushed onto a list, for `blpop`.
+    pushed: Arc<Condvar>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+}
+
+/// A subscription to a `MemoryBackend` channel.
+pub struct MemorySubscription {
+    rx: Receiver<String>,
+}
+
+impl Subscription for MemorySubscription {
+    fn next_message(&mut self) -> Result<String> {
+        Ok(self.rx.recv()?)
+    }
+}
+
+/// Matches a key against a glob-style pattern,
+/// where `*` matches any sequence of characters.
+fn glob_match(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !key.starts_with(first) {
+        return false;
+    }
+    let mut rest = &key[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+/// The value at the key a member names, if any.
+fn value_at(store: &Store, member: &[u8]) -> Option<Vec<u8>> {
+    String::from_utf8(member.to_vec()).ok().and_then(|key| store.values.get(&key).cloned())
+}
+
+/// Pair up a flat list of members and values, as returned by Redis.
+fn pairs(flat: Vec<Option<Vec<u8>>>) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut pairs = Vec::with_capacity(flat.len() / 2);
+    let mut flat = flat.into_iter();
+    while let (Some(member), Some(value)) = (flat.next(), flat.next()) {
+        pairs.push((member.unwrap_or_default(), value));
+    }
+    pairs
+}
+
+/// Pop a value from the head of a list in the store, if any.
+fn pop_front(store: &mut Store, key: &str) -> Option<Vec<u8>> {
+    let (value, empty) = match store.lists.get_mut(key) {
+        Some(list) => (list.pop_front(), list.is_empty()),
+        None => (None, false),
+    };
+
+    // redis deletes empty lists
+    if empty {
+        store.lists.remove(key);
+    }
+    value
+}
+
+impl Backend for MemoryBackend {
+    type Subscription = MemorySubscription;
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let store = self.store.lock()?;
+        Ok(store.values.get(key).cloned())
+    }
+
+    fn get_multiple(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>> {
+        let store = self.store.lock()?;
+        Ok(keys.iter().map(|key| store.values.get(key).cloned()).collect())
+    }
+
+    fn set(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        let mut store = self.store.lock()?;
+        store.values.insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    fn set_multiple(&self, items: &[(String, Vec<u8>)]) -> Result<()> {
+        let mut store = self.store.lock()?;
+        for &(ref key, ref value) in items {
+            store.values.insert(key.clone(), value.clone());
+        }
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> Result<()> {
+        let mut store = self.store.lock()?;
+        store.values.remove(key);
+        store.sets.remove(key);
+        store.lists.remove(key);
+        Ok(())
+    }
+
+    fn del_multiple(&self, keys: &[String]) -> Result<()> {
+        for key in keys {
+            self.del(key)?;
+        }
+        Ok(())
+    }
+
+    fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let store = self.store.lock()?;
+        Ok(store.values
+            .keys()
+            .chain(store.sets.keys())
+            .chain(store.lists.keys())
+            .filter(|key| glob_match(pattern, key))
+            .cloned()
+            .collect())
+    }
+
+    fn sadd(&self, key: &str, members: Vec<Vec<u8>>) -> Result<()> {
+        if !members.is_empty() {
+            let mut store = self.store.lock()?;
+            store.sets.entry(key.to_owned()).or_insert_with(FnvHashSet::default).extend(members);
+        }
+        Ok(())
+    }
+
+    fn srem(&self, key: &str, members: Vec<Vec<u8>>) -> Result<()> {
+        let mut store = self.store.lock()?;
+        let empty = match store.sets.get_mut(key) {
+            Some(set) => {
+                for member in members {
+                    set.remove(&member);
+                }
+                set.is_empty()
+            }
+            None => false,
+        };
+
+        // redis deletes empty sets
+        if empty {
+            store.sets.remove(key);
+        }
+        Ok(())
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+        let store = self.store.lock()?;
+        Ok(match store.sets.get(key) {
+            Some(set) => set.iter().cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
+    fn smembers_multiple(&self, keys: &[String]) -> Result<Vec<Vec<Vec<u8>>>> {
+        keys.iter().map(|key| self.smembers(key)).collect()
+    }
+
+    fn smembers_values(&self, key: &str) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let store = self.store.lock()?;
+        Ok(match store.sets.get(key) {
+            Some(set) => set.iter().map(|member| (member.clone(), value_at(&store, member))).collect(),
+            None => Vec::new(),
+        })
+    }
+
+    fn scard(&self, key: &str) -> Result<usize> {
+        let store = self.store.lock()?;
+        Ok(store.sets.get(key).map_or(0, |set| set.len()))
+    }
+
+    fn srandmember(&self, key: &str, count: usize) -> Result<Vec<Vec<u8>>> {
+        let store = self.store.lock()?;
+        Ok(match store.sets.get(key) {
+            Some(set) => {
+                let mut rng = rand::thread_rng();
+                rand::sample(&mut rng, set.iter().cloned(), count)
+            }
+            None => Vec::new(),
+        })
+    }
+
+    fn srandmember_values(&self, key: &str, count: usize) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let store = self.store.lock()?;
+        Ok(match store.sets.get(key) {
+            Some(set) => {
+                let mut rng = rand::thread_rng();
+                rand::sample(&mut rng, set.iter(), count)
+                    .into_iter()
+                    .map(|member| (member.clone(), value_at(&store, member)))
+                    .collect()
+            }
+            None => Vec::new(),
+        })
+    }
+
+    /// Everything is in-process, so this returns all members at once.
+    fn sscan(&self, key: &str, _cursor: u64, _count: usize) -> Result<(u64, Vec<Vec<u8>>)> {
+        Ok((0, self.smembers(key)?))
+    }
+
+    fn lpush(&self, key: &str, values: Vec<Vec<u8>>) -> Result<()> {
+        if !values.is_empty() {
+            let mut store = self.store.lock()?;
+            let list = store.lists.entry(key.to_owned()).or_insert_with(VecDeque::new);
+            for value in values {
+                list.push_front(value);
+            }
+            self.pushed.notify_all();
+        }
+        Ok(())
+    }
+
+    fn lpop(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut store = self.store.lock()?;
+        Ok(pop_front(&mut store, key))
+    }
+
+    fn lrange(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+        let store = self.store.lock()?;
+        Ok(match store.lists.get(key) {
+            Some(list) => list.iter().cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
+    fn take_lists(&self, keys: &[String]) -> Result<Vec<Vec<Vec<u8>>>> {
+        let mut store = self.store.lock()?;
+        Ok(keys.iter()
+            .map(|key| store.lists.remove(key).map_or(Vec::new(), |list| list.into_iter().collect()))
+            .collect())
+    }
+
+    fn blpop(&self, keys: &[String], timeout: Duration) -> Result<Option<(String, Vec<u8>)>> {
+        let started = Instant::now();
+        let mut store = self.store.lock()?;
+        loop {
+            for key in keys {
+                if let Some(value) = pop_front(&mut store, key) {
+                    return Ok(Some((key.clone(), value)));
+                }
+            }
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Ok(None);
+            }
+            store = self.pushed.wait_timeout(store, timeout - elapsed)?.0;
+        }
+    }
+
+    fn apply(&self, ops: Vec<BackendOp>) -> Result<()> {
+        for op in ops {
+            match op {
+                BackendOp::Set(key, val) => self.set(&key, val)?,
+                BackendOp::Del(key) => self.del(&key)?,
+                BackendOp::Add(key, val) => self.sadd(&key, vec![val])?,
+                BackendOp::Remove(key, val) => self.srem(&key, vec![val])?,
+                BackendOp::Push(key, vals) => self.lpush(&key, vals)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn publish(&self, channel: &str, msg: &str) -> Result<()> {
+        let mut store = self.store.lock()?;
+        if let Some(subscribers) = store.channels.get_mut(channel) {
+            // drop subscribers that have gone away
+            subscribers.retain(|tx| tx.send(msg.to_owned()).is_ok());
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self, channel: &str) -> Result<MemorySubscription> {
+        let (tx, rx) = mpsc::channel();
+        let mut store = self.store.lock()?;
+        store.channels.entry(channel.to_owned()).or_insert_with(Vec::new).push(tx);
+        Ok(MemorySubscription { rx: rx })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_literals() {
+        assert!(glob_match("workers", "workers"));
+        assert!(!glob_match("workers", "worker"));
+        assert!(!glob_match("worker", "workers"));
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("idx:*", "idx:people"));
+        assert!(!glob_match("idx:*", "updates:0"));
+        assert!(glob_match("*:members", "space:s:members"));
+        assert!(glob_match("grid:*:occ:*", "grid:g:occ:1:2"));
+        assert!(!glob_match("grid:*:occ:*", "grid:g:cell:1:2"));
+        assert!(glob_match("load:*:*", "load:run:3"));
+        assert!(!glob_match("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn take_lists_empties_lists() {
+        let conn = MemoryBackend::new();
+        conn.lpush("a", vec![b"1".to_vec(), b"2".to_vec()]).unwrap();
+        conn.lpush("b", vec![b"3".to_vec()]).unwrap();
+        let keys = vec!["a".to_owned(), "missing".to_owned(), "b".to_owned()];
+        let lists = conn.take_lists(&keys).unwrap();
+
+        // head first, i.e. last pushed first
+        assert_eq!(lists, vec![vec![b"2".to_vec(), b"1".to_vec()], vec![], vec![b"3".to_vec()]]);
+        assert!(conn.keys("*").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use ser::{decode, encode};
+use error::Result;
+
+/// A snapshot of a simulation run between two steps.
+///
+/// Values are stored as they are in the backend (i.e. still encoded),
+/// so this doesn't depend on the simulation's types.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    /// The step the run should continue from.
+    pub step: usize,
+
+    /// The run's seed.
+    pub seed: u64,
+
+    pub world: Vec<u8>,
+
+    /// Agent states, by id.
+    pub agents: Vec<(u64, Vec<u8>)>,
+
+    /// Agent ids, by index name.
+    pub indices: Vec<(String, Vec<u64>)>,
+
+    /// Queued spawns and kills.
+    pub pop_updates: Vec<Vec<u8>>,
+
+    /// Queued world updates.
+    pub world_updates: Vec<Vec<u8>>,
+
+    /// Queued `(id, stamp, update)` agent updates.
+    pub agent_updates: Vec<Vec<u8>>,
+
+    /// Agent updates delayed until a later step, as `(step, update)`.
+    #[serde(default)]
+    pub pending_updates: Vec<(usize, Vec<u8>)>,
+
+    /// World updates delayed until a later step, as `(step, update)`.
+    #[serde(default)]
+    pub pending_world_updates: Vec<(usize, Vec<u8>)>,
+
+    /// Steps that something is due at, including agents waking up in discrete-event mode.
+    #[serde(default)]
+    pub pending: Vec<usize>,
+
+    /// Sleeping agents, as `(id, step it fell asleep, step it wakes up at)`.
+    #[serde(default)]
+    pub dormant: Vec<(u64, usize, Option<usize>)>,
+
+    /// Sets stored alongside the population (e.g. grid occupants), by key.
+    #[serde(default)]
+    pub sets: Vec<(String, Vec<Vec<u8>>)>,
+
+    /// Values stored alongside the population (e.g. grid cells), by key.
+    #[serde(default)]
+    pub values: Vec<(String, Vec<u8>)>,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint to a file.
+    /// The file is replaced only once the checkpoint is fully written,
+    /// so a crash mid-write leaves the previous checkpoint intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = File::create(&tmp)?;
+            f.write_all(&encode(self)?)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Read a checkpoint from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        decode(data)
+    }
+}
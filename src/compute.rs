@@ -1,44 +1,236 @@
 use uuid::Uuid;
-use std::{thread, time};
-use redis::{Commands, Client, Connection, PubSub};
-use hash::{WHasher, hash};
-use ser::{decode, encode};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::any::Any;
+use std::cmp;
+use std::mem;
+use std::str::FromStr;
+use std::string::ToString;
+use std::path::{Path, PathBuf};
+use std::panic::{self, AssertUnwindSafe};
+use rand::{self, Rng, XorShiftRng};
+use hash::{WHasher, mix, place, seeded_rng};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ser::{Codec, decode, encode};
 use sim::{Agent, Simulation, State};
+use backend::{Backend, BackendOp, Subscription};
+use error::{DjinnError, Result};
+use heartbeat::{Heartbeat, HeartbeatMonitor, FailurePolicy};
+use checkpoint::Checkpoint;
+use schedule::Schedule;
+use migrate::Migrations;
+use stop::{StopCondition, StopReason, RunSummary, RunProgress};
+use record::{Recorder, Recording, RecordFormat, Row, Value, AgentWriter, write_rows};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
 use fnv::FnvHashMap;
 
-/// An interface to a Redis instance or cluster.
-pub trait Redis: Commands + Send + Sync + Clone {}
-impl<T> Redis for T where T: Commands + Send + Sync + Clone {}
-
 const POPULATION_KEY: &'static str = "population";
 const POP_UPDATES_KEY: &'static str = "updates:population";
 const WORLD_UPDATES_KEY: &'static str = "updates:world";
+const SEED_KEY: &'static str = "seed";
+
+/// Patterns for the keys of sets and values stored alongside the population
+/// (e.g. by a `Grid`), which are included in checkpoints.
+const STRUCTURE_SET_KEYS: [&'static str; 5] = ["grid:*:occ:*",
+                                                "space:*:bin:*",
+                                                "space:*:members",
+                                                "graph:*:out:*",
+                                                "graph:*:in:*"];
+const STRUCTURE_VALUE_KEYS: [&'static str; 3] = ["grid:*:cell:*", "space:*:pos:*", "graph:*:w:*"];
+
+/// Set of the steps that delayed updates (or, in discrete-event mode, agents) are due at.
+const PENDING_KEY: &'static str = "pending";
+
+/// How many agents to read at a time when exporting the population.
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+/// Sender id for updates and spawns queued by the world.
+const WORLD_SENDER: u64 = u64::MAX;
+
+/// Sender id for agents spawned by the manager.
+const MANAGER_SENDER: u64 = u64::MAX - 1;
+
+/// Salt for a deciding sender's draws through `Population::random(s)`,
+/// kept separate from its draws through `Updates::rng`.
+const DRAWS_SALT: u64 = 0x6472_6177_735f_6b65;
+
+/// Orders updates sent to an agent: the rank of the sender (see `Schedule::rank`)
+/// and the sequence number of the update among those it sent.
+/// Updates are applied in this order, so that it doesn't depend on
+/// how agents are spread across workers.
+type Stamp = (u64, usize);
+
+/// Derive the key for a sender's randomness (and spawned agent ids) at a step.
+fn sender_key(seed: u64, step: usize, sender: u64) -> u64 {
+    mix(mix(seed, step as u64), sender)
+}
+
+/// Key for the list of updates for a worker's agents that are due at a later step.
+fn pending_key(step: usize, worker_id: usize) -> String {
+    format!("pending:{}:{}", step, worker_id)
+}
+
+/// Key for the sleeping agents of a slot, saved after each update phase
+/// so they stay asleep when the slot moves to another worker or the run is resumed.
+fn dormant_key(slot: usize) -> String {
+    format!("dormant:{}", slot)
+}
+
+/// Key for the list of world updates that are due at a later step.
+fn pending_world_key(step: usize) -> String {
+    format!("pending_world:{}", step)
+}
+
+/// Encode an id (or any other plain value) as a set or list member.
+pub fn member<T: ToString>(val: T) -> Vec<u8> {
+    val.to_string().into_bytes()
+}
+
+/// Decode a set or list member created with `member`.
+pub fn from_member<T: FromStr>(data: Vec<u8>) -> Result<T> {
+    match String::from_utf8(data).ok().and_then(|s| s.parse().ok()) {
+        Some(val) => Ok(val),
+        None => Err(DjinnError::Protocol("malformed member".to_owned())),
+    }
+}
+
+/// Decode a list of members created with `member`.
+fn from_members<T: FromStr>(datas: Vec<Vec<u8>>) -> Result<Vec<T>> {
+    datas.into_iter().map(from_member).collect()
+}
+
+/// Encode a list of values.
+fn encode_all<C: Codec, T: Serialize>(vals: Vec<T>) -> Result<Vec<Vec<u8>>> {
+    vals.iter().map(|val| C::encode(val)).collect()
+}
+
+/// Decode a list of values.
+fn decode_all<C: Codec, T: DeserializeOwned>(datas: Vec<Vec<u8>>) -> Result<Vec<T>> {
+    datas.iter().map(|data| C::decode(data)).collect()
+}
 
 /// A container for queuing and synchronizing agent updates.
 ///
 /// Updates for agents local to the worker will be directly routed to those agents.
 /// Updates for remote agents will be synchronized via Redis.
+///
+/// This also provides the deciding agent with its source of randomness, see `rng`.
 pub struct Updates<S: Simulation> {
-    updates: FnvHashMap<usize, Vec<(u64, S::Update)>>,
+    updates: FnvHashMap<usize, Vec<(u64, Stamp, S::Update)>>,
+    delayed: FnvHashMap<(usize, usize), Vec<(u64, Stamp, S::Update)>>,
     world_updates: Vec<S::Update>,
+    delayed_world: FnvHashMap<usize, Vec<S::Update>>,
     pop_updates: Vec<PopulationUpdate<S::State>>,
+    ops: Vec<BackendOp>,
     hasher: WHasher,
+    seed: u64,
+    key: u64,
+    sender: u64,
+    rank: u64,
+    schedule: Schedule,
+    step: usize,
+    sleeps: Vec<(u64, Option<usize>)>,
+    n_queued: usize,
+    n_spawned: u64,
+    rng: XorShiftRng,
 }
 
 impl<S: Simulation> Updates<S> {
     pub fn new(hasher: WHasher) -> Updates<S> {
         Updates {
             updates: FnvHashMap::default(),
+            delayed: FnvHashMap::default(),
             world_updates: Vec::new(),
+            delayed_world: FnvHashMap::default(),
             pop_updates: Vec::new(),
+            ops: Vec::new(),
             hasher: hasher,
+            seed: 0,
+            key: 0,
+            sender: WORLD_SENDER,
+            rank: WORLD_SENDER,
+            schedule: Schedule::Synchronous,
+            step: 0,
+            sleeps: Vec::new(),
+            n_queued: 0,
+            n_spawned: 0,
+            rng: seeded_rng(0),
         }
     }
 
+    /// Prepare for a sender (an agent id, or the world) to decide at a step.
+    fn begin(&mut self, step: usize, sender: u64) {
+        self.key = sender_key(self.seed, step, sender);
+        self.sender = sender;
+        self.rank = self.schedule.rank(self.key, sender);
+        self.step = step;
+        self.n_queued = 0;
+        self.n_spawned = 0;
+        self.rng = seeded_rng(self.key);
+    }
+
+    /// A random number generator for the deciding agent (or world).
+    ///
+    /// It's seeded from the run's seed, the current step, and the agent's id,
+    /// so runs with the same seed make the same random draws,
+    /// regardless of how many workers they're run across.
+    pub fn rng(&mut self) -> &mut XorShiftRng {
+        &mut self.rng
+    }
+
+    /// The step being decided.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
     /// Queue a single update for an agent with the specified id.
     pub fn queue(&mut self, id: u64, update: S::Update) {
         let worker_id = self.hasher.hash(id);
-        self.updates.entry(worker_id).or_insert_with(Vec::new).push((id, update));
+        let stamp = (self.rank, self.n_queued);
+        self.n_queued += 1;
+        self.updates.entry(worker_id).or_insert_with(Vec::new).push((id, stamp, update));
+    }
+
+    /// Queue an update for an agent, to be applied at the specified step
+    /// rather than at the end of this one. Updates for this step
+    /// or earlier ones are queued as usual.
+    pub fn queue_at(&mut self, id: u64, step: usize, update: S::Update) {
+        if step <= self.step {
+            return self.queue(id, update);
+        }
+        let worker_id = self.hasher.hash(id);
+        let stamp = (self.rank, self.n_queued);
+        self.n_queued += 1;
+        self.delayed.entry((step, worker_id)).or_insert_with(Vec::new).push((id, stamp, update));
+    }
+
+    /// Queue an update for an agent, to be applied `delay` steps after this one.
+    /// A delay of 0 is the same as `queue`.
+    pub fn queue_in(&mut self, id: u64, delay: usize, update: S::Update) {
+        let step = self.step + delay;
+        self.queue_at(id, step, update);
+    }
+
+    /// Put the deciding agent to sleep until an update arrives for it.
+    /// Sleeping agents don't decide, and don't update unless they receive an update.
+    ///
+    /// Updates applied in the step the agent falls asleep,
+    /// e.g. ones it queued for itself, don't wake it.
+    pub fn sleep(&mut self) {
+        if self.sender != WORLD_SENDER {
+            self.sleeps.push((self.sender, None));
+        }
+    }
+
+    /// Put the deciding agent to sleep until the specified step,
+    /// or until an update arrives for it, whichever comes first.
+    pub fn sleep_until(&mut self, step: usize) {
+        if self.sender != WORLD_SENDER {
+            self.sleeps.push((self.sender, Some(step)));
+        }
     }
 
     /// Queue an update for the world.
@@ -46,17 +238,52 @@ impl<S: Simulation> Updates<S> {
         self.world_updates.push(update);
     }
 
+    /// Queue an update for the world, to be applied at the specified step
+    /// rather than at the end of this one. Updates for this step
+    /// or earlier ones are queued as usual.
+    pub fn queue_world_at(&mut self, step: usize, update: S::Update) {
+        if step <= self.step {
+            return self.queue_world(update);
+        }
+        self.delayed_world.entry(step).or_insert_with(Vec::new).push(update);
+    }
+
+    /// Queue an update for the world, to be applied `delay` steps after this one.
+    /// A delay of 0 is the same as `queue_world`.
+    pub fn queue_world_in(&mut self, delay: usize, update: S::Update) {
+        let step = self.step + delay;
+        self.queue_world_at(step, update);
+    }
+
     /// Create a new agent with the specified state, returning the new agent's id.
     ///
     /// This does not actually spawn the agent, it just queues it.
     /// Run the `update` method to execute it (and other queued updates).
     pub fn spawn(&mut self, state: S::State) -> u64 {
-        let id = hash(&Uuid::new_v4().to_string());
+        self.n_spawned += 1;
+        let id = mix(self.key, self.n_spawned);
+        let update = PopulationUpdate::Spawn(id, state);
+        self.pop_updates.push(update);
+        id
+    }
+
+    /// Create a new agent with the specified state, like `spawn`,
+    /// but with its id moved to a part of the id space (a fraction in `[0, 1]`)
+    /// so that agents with nearby placements end up on the same worker,
+    /// e.g. agents in the same region of a `Grid` (see `Grid::placement`).
+    pub fn spawn_placed(&mut self, state: S::State, placement: f64) -> u64 {
+        self.n_spawned += 1;
+        let id = place(mix(self.key, self.n_spawned), placement);
         let update = PopulationUpdate::Spawn(id, state);
         self.pop_updates.push(update);
         id
     }
 
+    /// Queue a write to the backend, applied in the update phase.
+    pub fn queue_op(&mut self, op: BackendOp) {
+        self.ops.push(op);
+    }
+
     /// Deletes an agent by id.
     ///
     /// This does not actually execute the kill, it just queues it.
@@ -68,201 +295,416 @@ impl<S: Simulation> Updates<S> {
     }
 
     fn clear(&mut self) {
+        self.sleeps.clear();
         self.world_updates.clear();
         self.updates.clear();
+        self.delayed.clear();
+        self.delayed_world.clear();
         self.pop_updates.clear();
+        self.ops.clear();
     }
 
-    /// Push these local updates to Redis.
-    fn push<R: Redis>(&mut self, pop: &Population<S, R>) {
-        for (worker_id, mut updates) in self.updates.drain() {
-            let key = format!("updates:{}", worker_id);
-            let encoded: Vec<Vec<u8>> = updates.drain(..).map(|u| encode(&u).unwrap()).collect();
-            let _: () = pop.conn
-                .lpush(key, encoded)
-                .unwrap();
+    /// Push these local updates to the backend.
+    fn push<B: Backend>(&mut self, pop: &Population<S, B>) -> Result<()> {
+        let res = self.push_all(pop);
+        self.clear();
+        res
+    }
+
+    fn push_all<B: Backend>(&mut self, pop: &Population<S, B>) -> Result<()> {
+        let mut ops = Vec::new();
+        for (worker_id, updates) in self.updates.drain() {
+            ops.push(BackendOp::Push(format!("updates:{}", worker_id), encode_all::<S::Codec, _>(updates)?));
         }
-        if !self.pop_updates.is_empty() {
-            let pop_updates: Vec<Vec<u8>> =
-                self.pop_updates.drain(..).map(|u| encode(u).unwrap()).collect();
-            let _: () = pop.conn.sadd(POP_UPDATES_KEY, pop_updates).unwrap();
+        let mut due = Vec::new();
+        for ((step, worker_id), updates) in self.delayed.drain() {
+            ops.push(BackendOp::Push(pending_key(step, worker_id), encode_all::<S::Codec, _>(updates)?));
+            due.push(step);
         }
-        if !self.world_updates.is_empty() {
-            let world_updates: Vec<Vec<u8>> =
-                self.world_updates.drain(..).map(|u| encode(u).unwrap()).collect();
-            let _: () = pop.conn.sadd(WORLD_UPDATES_KEY, world_updates).unwrap();
+        for (step, updates) in self.delayed_world.drain() {
+            ops.push(BackendOp::Push(pending_world_key(step), encode_all::<S::Codec, _>(updates)?));
+            due.push(step);
         }
-        self.clear();
+        due.sort();
+        due.dedup();
+        ops.extend(due.into_iter().map(|step| BackendOp::Add(PENDING_KEY.to_owned(), member(step))));
+        for update in encode_all::<S::Codec, _>(self.pop_updates.drain(..).collect())? {
+            ops.push(BackendOp::Add(POP_UPDATES_KEY.to_owned(), update));
+        }
+        for update in encode_all::<S::Codec, _>(self.world_updates.drain(..).collect())? {
+            ops.push(BackendOp::Add(WORLD_UPDATES_KEY.to_owned(), update));
+        }
+
+        // send everything in one go
+        pop.conn.apply(ops)
     }
 }
 
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(bound = "")]
 pub enum PopulationUpdate<S: State> {
     Spawn(u64, S),
     Kill(u64, S),
 }
 
-/// An interface to the Redis-backed agent population.
+/// A worker's agents, by slot.
+type LocalAgents<T> = FnvHashMap<usize, FnvHashMap<u64, Agent<T>>>;
+
+/// An interface to the backend-stored agent population.
 #[derive(Clone)]
-pub struct Population<S: Simulation, C: Redis> {
-    pub conn: C,
+pub struct Population<S: Simulation, B: Backend> {
+    pub conn: B,
     simulation: S,
     hasher: Option<WHasher>,
+    migrations: Arc<Migrations<S>>,
+
+    /// While a worker's agents are deciding, the worker's own agents,
+    /// which are read from directly rather than from the backend.
+    local: Option<Arc<LocalAgents<S::State>>>,
+
+    /// While a worker's agents are deciding, other agents read so far.
+    /// States can't change until the update phase, so these stay fresh until then.
+    cache: Option<Arc<Mutex<FnvHashMap<u64, Agent<S::State>>>>>,
+
+    /// While a worker's agents are deciding, the members (ids, sorted)
+    /// of each index read so far, as of the first time it was read.
+    members: Option<Arc<Mutex<FnvHashMap<String, Arc<Vec<u64>>>>>>,
+
+    /// While an agent (or the world) is deciding, the random stream
+    /// `random` and `randoms` draw from, derived from its key for the step.
+    draws: Option<Arc<Mutex<XorShiftRng>>>,
 }
 
-impl<S: Simulation, C: Redis> Population<S, C> {
-    pub fn new(simulation: S, conn: C) -> Population<S, C> {
+impl<S: Simulation, B: Backend> Population<S, B> {
+    pub fn new(simulation: S, conn: B) -> Population<S, B> {
         Population {
             conn: conn,
+            migrations: Arc::new(simulation.migrations()),
             simulation: simulation,
             hasher: None,
+            local: None,
+            cache: None,
+            members: None,
+            draws: None,
         }
     }
 
     /// Count the population size.
-    pub fn count(&self) -> usize {
-        self.conn.scard::<&str, usize>(POPULATION_KEY).unwrap()
+    pub fn count(&self) -> Result<usize> {
+        self.conn.scard(POPULATION_KEY)
     }
 
     /// Get the world (state).
-    pub fn world(&self) -> S::World {
-        let data = self.conn.get("world").unwrap();
-        decode(data).unwrap()
+    pub fn world(&self) -> Result<S::World> {
+        match self.conn.get("world")? {
+            Some(data) => S::Codec::decode(&data),
+            None => Err(DjinnError::Protocol("the world has not been set".to_owned())),
+        }
     }
 
     /// Set the world state.
-    pub fn set_world(&self, world: S::World) {
-        let data = encode(&world).unwrap();
-        let _: () = self.conn.set("world", data).unwrap();
+    pub fn set_world(&self, world: S::World) -> Result<()> {
+        let data = S::Codec::encode(&world)?;
+        self.conn.set("world", data)
     }
 
-    /// Get an agent by id.
-    pub fn get_agent(&self, id: u64) -> Option<Agent<S::State>> {
-        let data = self.conn.get(id).unwrap();
-        Some(Agent {
-            id: id,
-            state: decode(data).unwrap(),
-        })
+    /// Serve reads from a worker's own agents and a cache of other agents' states,
+    /// until `end_reads`. Only for the decide phase, when states can't change.
+    fn begin_reads(&mut self, local: Arc<LocalAgents<S::State>>) {
+        self.local = Some(local);
+        self.cache = Some(Arc::new(Mutex::new(FnvHashMap::default())));
+        self.members = Some(Arc::new(Mutex::new(FnvHashMap::default())));
+    }
+
+    /// Go back to reading everything from the backend,
+    /// handing back the worker's agents.
+    fn end_reads(&mut self) -> Option<Arc<LocalAgents<S::State>>> {
+        self.cache = None;
+        self.members = None;
+        self.draws = None;
+        self.local.take()
+    }
+
+    /// Draw random agents reproducibly for a deciding sender, given its key for the step
+    /// (see `Updates::rng`), until `end_draws` or `end_reads`.
+    fn begin_draws(&mut self, key: u64) {
+        self.draws = Some(Arc::new(Mutex::new(seeded_rng(mix(key, DRAWS_SALT)))));
+    }
+
+    fn end_draws(&mut self) {
+        self.draws = None;
+    }
+
+    /// Get an agent without going to the backend,
+    /// if it's one of this worker's or has already been read.
+    fn cached(&self, id: u64) -> Result<Option<Agent<S::State>>> {
+        if let (Some(ref local), Some(ref hasher)) = (self.local.as_ref(), self.hasher.as_ref()) {
+            if let Some(agent) = local.get(&hasher.hash(id)).and_then(|agents| agents.get(&id)) {
+                return Ok(Some(agent.clone()));
+            }
+        }
+        match self.cache {
+            Some(ref cache) => Ok(cache.lock()?.get(&id).cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// The members (ids, sorted) of an index, from the backend
+    /// or, if caching and it's been read before, from the cache.
+    fn index_members(&self, index: &str) -> Result<Arc<Vec<u64>>> {
+        if let Some(ref members) = self.members {
+            if let Some(ids) = members.lock()?.get(index) {
+                return Ok(ids.clone());
+            }
+        }
+        let mut ids: Vec<u64> = from_members(self.conn.smembers(&format!("idx:{}", index))?)?;
+        ids.sort();
+        let ids = Arc::new(ids);
+        if let Some(ref members) = self.members {
+            members.lock()?.insert(index.to_owned(), ids.clone());
+        }
+        Ok(ids)
+    }
+
+    /// Hold on to agents read from the backend, if caching.
+    fn remember(&self, agents: &[Agent<S::State>]) -> Result<()> {
+        if let Some(ref cache) = self.cache {
+            let mut cache = cache.lock()?;
+            for agent in agents {
+                cache.insert(agent.id, agent.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Get an agent by id, if it exists.
+    pub fn get_agent(&self, id: u64) -> Result<Option<Agent<S::State>>> {
+        if let Some(agent) = self.cached(id)? {
+            return Ok(Some(agent));
+        }
+        match self.conn.get(&id.to_string())? {
+            Some(data) => {
+                let agent = Agent {
+                    id: id,
+                    state: self.migrations.decode(&data)?,
+                };
+                self.remember(&[agent.clone()])?;
+                Ok(Some(agent))
+            }
+            None => Ok(None),
+        }
     }
 
     /// Get agents by ids.
     /// If you need to fetch multiple agents, you should use this as it makes only one network
     /// request.
+    ///
+    /// Fails with `DjinnError::MissingAgent` if any of the agents do not exist.
     // TODO this will probably not work with a redis cluster b/c each id hashes to a different
     // slot...
-    pub fn get_agents(&self, ids: Vec<u64>) -> Vec<Agent<S::State>> {
+    pub fn get_agents(&self, ids: Vec<u64>) -> Result<Vec<Agent<S::State>>> {
         if ids.len() == 1 {
             let id = ids[0];
-            let agent = self.get_agent(id).unwrap();
-            vec![agent]
+            match self.get_agent(id)? {
+                Some(agent) => Ok(vec![agent]),
+                None => Err(DjinnError::MissingAgent(id)),
+            }
         } else if !ids.is_empty() {
-            let datas = self.conn.get::<Vec<u64>, Vec<Vec<u8>>>(ids.clone()).unwrap();
-            ids.iter()
-                .zip(datas)
-                .map(|(id, data)| {
-                    Agent {
-                        id: *id,
-                        state: decode(data).unwrap(),
+            let mut agents = Vec::with_capacity(ids.len());
+            let mut missing = Vec::new();
+            for (i, id) in ids.iter().enumerate() {
+                let agent = self.cached(*id)?;
+                if agent.is_none() {
+                    missing.push(i);
+                }
+                agents.push(agent);
+            }
+
+            // fetch the rest in one request
+            let keys: Vec<String> = missing.iter().map(|i| ids[*i].to_string()).collect();
+            let datas = self.conn.get_multiple(&keys)?;
+            let mut fetched = Vec::with_capacity(missing.len());
+            for (i, data) in missing.into_iter().zip(datas) {
+                match data {
+                    Some(data) => {
+                        fetched.push(Agent {
+                            id: ids[i],
+                            state: self.migrations.decode(&data)?,
+                        });
+                        agents[i] = fetched.last().cloned();
                     }
-                })
-                .collect()
+                    None => return Err(DjinnError::MissingAgent(ids[i])),
+                }
+            }
+            self.remember(&fetched)?;
+            Ok(agents.into_iter().filter_map(|agent| agent).collect())
         } else {
-            Vec::new()
+            Ok(Vec::new())
+        }
+    }
+
+    /// Decode agents from ids fetched along with their states.
+    ///
+    /// Fails with `DjinnError::MissingAgent` if any of the agents do not exist.
+    fn agents_from(&self, pairs: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<Vec<Agent<S::State>>> {
+        pairs.into_iter()
+            .map(|(id, data)| {
+                let id = from_member(id)?;
+                match data {
+                    Some(data) => {
+                        Ok(Agent {
+                            id: id,
+                            state: self.migrations.decode(&data)?,
+                        })
+                    }
+                    None => Err(DjinnError::MissingAgent(id)),
+                }
+            })
+            .collect()
+    }
+
+    /// Write every agent (id and state) to `w`, reading them from the backend in batches.
+    ///
+    /// JSON-lines output starts with the world, as `{"world": ...}`,
+    /// followed by an `{"id": ..., "state": ...}` line per agent.
+    /// CSV output has a column for the id and for each (flattened) field of any agent's state,
+    /// and doesn't include the world. Nested fields, including those of enum struct variants,
+    /// get `parent.child` columns. Rows are written as they're read, so to find every agent's
+    /// fields for the header, the population is read twice.
+    pub fn export<W: Write>(&self, w: W, format: RecordFormat) -> Result<()> {
+        let mut writer = AgentWriter::new(w, format);
+        writer.write_world(&self.world()?)?;
+        if format == RecordFormat::Csv {
+            self.for_each_agent(|_, state| writer.add_columns(&state))?;
+        }
+        self.for_each_agent(|id, state| writer.write_agent(id, &state))?;
+        writer.flush()
+    }
+
+    /// Read every agent from the backend, in batches.
+    fn for_each_agent<F>(&self, mut func: F) -> Result<()>
+        where F: FnMut(u64, S::State) -> Result<()>
+    {
+        let mut cursor = 0;
+        loop {
+            let (next, members) = self.conn.sscan(POPULATION_KEY, cursor, EXPORT_BATCH_SIZE)?;
+            let ids: Vec<u64> = from_members(members)?;
+            let keys: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            let datas = self.conn.get_multiple(&keys)?;
+            for (id, data) in ids.into_iter().zip(datas) {
+                if let Some(data) = data {
+                    func(id, self.migrations.decode(&data)?)?;
+                }
+            }
+            cursor = next;
+            if cursor == 0 {
+                return Ok(());
+            }
         }
     }
 
     /// Set an agent state by id.
-    pub fn set_agent(&self, id: u64, state: &S::State) {
-        let data = encode(state).unwrap();
-        let _: () = self.conn.set(id, data).unwrap();
+    pub fn set_agent(&self, id: u64, state: &S::State) -> Result<()> {
+        let data = self.migrations.encode(state)?;
+        self.conn.set(&id.to_string(), data)
     }
 
     /// Set multple agent states by ids.
     /// If you need to update multiple agents, you should use this as it makes only one network
     /// request.
-    pub fn set_agents(&self, updates: &Vec<(u64, &S::State)>) {
+    pub fn set_agents(&self, updates: &Vec<(u64, &S::State)>) -> Result<()> {
         if updates.len() == 1 {
             let (id, ref state) = updates[0];
-            self.set_agent(id, state);
+            self.set_agent(id, state)
         } else if !updates.is_empty() {
-            let encoded: Vec<(u64, Vec<u8>)> = updates.iter()
-                .map(|&(id, ref state)| (id, encode(state).unwrap()))
-                .collect();
-            let _: () = self.conn.set_multiple(encoded.as_slice()).unwrap();
+            let mut encoded: Vec<(String, Vec<u8>)> = Vec::with_capacity(updates.len());
+            for &(id, ref state) in updates {
+                encoded.push((id.to_string(), self.migrations.encode(state)?));
+            }
+            self.conn.set_multiple(encoded.as_slice())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn hasher(&self) -> Result<&WHasher> {
+        match self.hasher {
+            Some(ref hasher) => Ok(hasher),
+            None => Err(DjinnError::Protocol("the population is not assigned to workers".to_owned())),
         }
     }
 
-    fn spawns(&self, to_spawn: Vec<(u64, S::State)>) {
+    fn spawns(&self, to_spawn: Vec<(u64, S::State)>) -> Result<()> {
         if !to_spawn.is_empty() {
-            let ids: Vec<u64> = to_spawn.iter().map(|&(id, _)| id).collect();
-            let _: () = self.conn.sadd(POPULATION_KEY, ids).unwrap();
+            let mut ops = Vec::with_capacity(to_spawn.len() * 2);
 
             // map the workers we need to send new agents to
-            let hasher = self.hasher.as_ref().unwrap();
+            let hasher = self.hasher()?;
             let mut targets: FnvHashMap<usize, Vec<Vec<u8>>> = FnvHashMap::default();
-            let agents = to_spawn.iter()
-                .map(|&(id, ref state)| {
-                    let a = Agent {
-                        id: id,
-                        state: state.clone(),
-                    };
-                    targets.entry(hasher.hash(id))
-                        .or_insert_with(Vec::new)
-                        .push(encode(&a).unwrap());
-                    a
-                })
-                .collect();
-            let to_spawn_ref = to_spawn.iter().map(|&(id, ref state)| (id, state)).collect(); // TODO there is likely a way to do this differently?
-            self.set_agents(&to_spawn_ref);
-
+            let mut agents = Vec::with_capacity(to_spawn.len());
+            for (id, state) in to_spawn {
+                ops.push(BackendOp::Add(POPULATION_KEY.to_owned(), member(id)));
+                ops.push(BackendOp::Set(id.to_string(), self.migrations.encode(&state)?));
+                let a = Agent {
+                    id: id,
+                    state: state,
+                };
+                targets.entry(hasher.hash(id))
+                    .or_insert_with(Vec::new)
+                    .push(S::Codec::encode(&a)?);
+                agents.push(a);
+            }
             for (worker_id, agents) in targets {
-                let key = format!("spawn:{}", worker_id);
-                let _: () = self.conn.lpush(key, agents).unwrap();
+                ops.push(BackendOp::Push(format!("spawn:{}", worker_id), agents));
             }
+            self.conn.apply(ops)?;
             self.simulation.on_spawns(agents, &self);
         }
+        Ok(())
     }
 
-    fn kills(&self, mut to_kill: Vec<(u64, S::State)>) {
+    fn kills(&self, mut to_kill: Vec<(u64, S::State)>) -> Result<()> {
         if !to_kill.is_empty() {
-            let ids: Vec<u64> = to_kill.iter().map(|&(id, _)| id).collect();
+            let mut ops = Vec::with_capacity(to_kill.len() * 2);
 
-            let _: () = self.conn.del(ids.clone()).unwrap();
-            let _: () = self.conn.srem(POPULATION_KEY, ids.clone()).unwrap();
-
-            let hasher = self.hasher.as_ref().unwrap();
-            let mut targets: FnvHashMap<usize, Vec<u64>> = FnvHashMap::default();
+            let hasher = self.hasher()?;
+            let mut targets: FnvHashMap<usize, Vec<Vec<u8>>> = FnvHashMap::default();
             let agents = to_kill.drain(..)
                 .map(|(id, state)| {
+                    ops.push(BackendOp::Del(id.to_string()));
+                    ops.push(BackendOp::Remove(POPULATION_KEY.to_owned(), member(id)));
                     let a = Agent {
                         id: id,
                         state: state,
                     };
                     targets.entry(hasher.hash(id))
                         .or_insert_with(Vec::new)
-                        .push(id);
+                        .push(member(id));
                     a
                 })
                 .collect();
             for (worker_id, ids) in targets.drain() {
-                let key = format!("kill:{}", worker_id);
-                let _: () = self.conn.lpush(key, ids).unwrap();
+                ops.push(BackendOp::Push(format!("kill:{}", worker_id), ids));
             }
+            self.conn.apply(ops)?;
 
             self.simulation.on_deaths(agents, &self);
         }
+        Ok(())
     }
 
     /// Process queued updates (kill/spawn).
-    pub fn update(&self) {
+    pub fn update(&self) -> Result<()> {
         let mut to_kill = Vec::new();
         let mut to_spawn = Vec::new();
 
-        let updates = self.conn.smembers::<&str, Vec<Vec<u8>>>(POP_UPDATES_KEY).unwrap();
-        let _: () = self.conn.del(POP_UPDATES_KEY).unwrap();
+        // sort so that spawns and kills are processed in a consistent order
+        let mut updates = self.conn.smembers(POP_UPDATES_KEY)?;
+        updates.sort();
+        self.conn.del(POP_UPDATES_KEY)?;
 
         for data in updates {
-            let update: PopulationUpdate<S::State> = decode(data).unwrap();
+            let update: PopulationUpdate<S::State> = S::Codec::decode(&data)?;
             match update {
                 PopulationUpdate::Kill(id, state) => {
                     to_kill.push((id, state));
@@ -273,413 +715,1844 @@ impl<S: Simulation, C: Redis> Population<S, C> {
             }
         }
 
-        self.kills(to_kill);
-        self.spawns(to_spawn);
+        self.kills(to_kill)?;
+        self.spawns(to_spawn)
     }
 
     /// Lookup agents at a particular index.
-    pub fn lookup(&self, index: &str) -> Vec<Agent<S::State>> {
-        let mut ids: Vec<u64> = self.conn.smembers(format!("idx:{}", index)).unwrap();
-        match ids.len() {
-            0 => Vec::new(),
-            1 => {
-                let id = ids.pop().unwrap();
-                let state_data: Vec<u8> = self.conn.get(id).unwrap();
-                let agent = Agent {
-                    id: id,
-                    state: decode(state_data).unwrap(),
-                };
-                vec![agent]
-            }
-            _ => {
-                ids.drain(..)
-                    .map(|id| {
-                        // TODO must be a bulk way of doing this?
-                        let state_data = self.conn.get(id).unwrap();
-                        Agent {
-                            id: id,
-                            state: decode(state_data).unwrap(),
-                        }
-                    })
-                    .collect()
-            }
-        }
+    pub fn lookup(&self, index: &str) -> Result<Vec<Agent<S::State>>> {
+        self.get_agents_by_index(index)
     }
 
-    /// Select a random agent from an index.
-    pub fn random(&self, index: &str) -> Agent<S::State> {
-        let id: u64 = self.conn.srandmember(format!("idx:{}", index)).unwrap();
-        let state_data: Vec<u8> = self.conn.get(id).unwrap();
-        Agent {
-            id: id,
-            state: decode(state_data).unwrap(),
+    /// Get the agents at an index, resolving the index
+    /// and fetching their states in one network request.
+    ///
+    /// While deciding, the index's members are only read once a step,
+    /// and only states that aren't local or already read are fetched.
+    ///
+    /// Fails with `DjinnError::MissingAgent` if any of the agents do not exist.
+    pub fn get_agents_by_index(&self, index: &str) -> Result<Vec<Agent<S::State>>> {
+        if self.members.is_some() {
+            let ids = self.index_members(index)?;
+            return self.get_agents(ids.to_vec());
         }
+        let pairs = self.conn.smembers_values(&format!("idx:{}", index))?;
+        self.agents_from(pairs)
+    }
+
+    /// Select a random agent from an index, if the index has any members.
+    ///
+    /// While deciding, this draws from a random stream of the deciding agent's own,
+    /// so it's reproducible just like `Updates::rng`. Elsewhere it isn't; see `random_with`.
+    pub fn random(&self, index: &str) -> Result<Option<Agent<S::State>>> {
+        let mut agents = self.randoms(index, 1)?;
+        Ok(agents.pop())
+    }
+
+    /// Select a random agent from an index, if the index has any members,
+    /// using the specified RNG, e.g. `updates.rng()`.
+    ///
+    /// This is reproducible for a seeded RNG, but it has to fetch the whole index.
+    pub fn random_with<R: Rng>(&self, index: &str, rng: &mut R) -> Result<Option<Agent<S::State>>> {
+        let mut ids = self.randoms_with(index, 1, rng)?;
+        Ok(ids.pop())
     }
 
-    /// Select random agents from an index.
-    pub fn randoms(&self, index: &str, count: usize) -> Vec<Agent<S::State>> {
-        let ids: Vec<u64> =
-            self.conn.srandmember_multiple(format!("idx:{}", index), count).unwrap();
+    /// Select random agents from an index using the specified RNG, e.g. `updates.rng()`.
+    ///
+    /// This is reproducible for a seeded RNG, but it has to fetch the whole index.
+    pub fn randoms_with<R: Rng>(&self,
+                                index: &str,
+                                count: usize,
+                                rng: &mut R)
+                                -> Result<Vec<Agent<S::State>>> {
+        let ids = self.index_members(index)?;
+        let ids = rand::sample(rng, ids.iter().cloned(), count);
         self.get_agents(ids)
     }
 
+    /// Select random agents from an index, fetching their states along with them.
+    ///
+    /// While deciding, they're drawn from a random stream of the deciding agent's own,
+    /// so the draws are reproducible just like `Updates::rng`. They're chosen from the index's
+    /// members as of the first time it was read this step, and only states that aren't local
+    /// or already read are fetched. Elsewhere the draws aren't reproducible; see `randoms_with`.
+    pub fn randoms(&self, index: &str, count: usize) -> Result<Vec<Agent<S::State>>> {
+        if let Some(ref draws) = self.draws {
+            let ids = self.index_members(index)?;
+            let ids = rand::sample(&mut *draws.lock()?, ids.iter().cloned(), count);
+            return self.get_agents(ids);
+        }
+        let pairs = self.conn.srandmember_values(&format!("idx:{}", index), count)?;
+        self.agents_from(pairs)
+    }
+
     /// Count the members of an index.
-    pub fn count_index(&self, index: &str) -> usize {
-        self.conn.scard(format!("idx:{}", index)).unwrap()
+    pub fn count_index(&self, index: &str) -> Result<usize> {
+        self.conn.scard(&format!("idx:{}", index))
     }
 
     /// Add an agent (id) to an index.
-    pub fn index(&self, index: &str, id: u64) {
-        let _: () = self.conn.sadd(format!("idx:{}", index), id).unwrap();
+    pub fn index(&self, index: &str, id: u64) -> Result<()> {
+        self.conn.sadd(&format!("idx:{}", index), vec![member(id)])
     }
 
     /// Add agents (ids) to an index.
-    pub fn indexes(&self, index: &str, ids: Vec<u64>) {
-        if !ids.is_empty() {
-            let _: () = self.conn.sadd(format!("idx:{}", index), ids).unwrap();
-        }
+    pub fn indexes(&self, index: &str, ids: Vec<u64>) -> Result<()> {
+        let ids = ids.into_iter().map(member).collect();
+        self.conn.sadd(&format!("idx:{}", index), ids)
     }
 
     /// Remove an agent (id) from an index.
-    pub fn unindex(&self, index: &str, id: u64) {
-        let _: () = self.conn.srem(format!("idx:{}", index), id).unwrap();
+    pub fn unindex(&self, index: &str, id: u64) -> Result<()> {
+        self.conn.srem(&format!("idx:{}", index), vec![member(id)])
     }
 
     /// Remove an agent (id) from an index.
-    pub fn unindexes(&self, index: &str, ids: Vec<u64>) {
-        if !ids.is_empty() {
-            let _: () = self.conn.srem(format!("idx:{}", index), ids).unwrap();
-        }
+    pub fn unindexes(&self, index: &str, ids: Vec<u64>) -> Result<()> {
+        let ids = ids.into_iter().map(member).collect();
+        self.conn.srem(&format!("idx:{}", index), ids)
     }
 
     /// Reset all indices.
-    pub fn reset_indices(&self) {
-        let keys: Vec<String> = self.conn.keys("idx:*").unwrap();
-        if !keys.is_empty() {
-            let _: () = self.conn.del(keys).unwrap();
-        }
+    pub fn reset_indices(&self) -> Result<()> {
+        let keys = self.conn.keys("idx:*")?;
+        self.conn.del_multiple(&keys)
     }
 
     /// Reset the population; i.e. deletes all agents and
-    /// updates from Redis and resets all indices.
-    pub fn reset(&self) {
+    /// updates from the backend and resets all indices.
+    pub fn reset(&self) -> Result<()> {
         // reset sets
-        let _: () = self.conn.del(POPULATION_KEY).unwrap();
-        let _: () = self.conn.del(POP_UPDATES_KEY).unwrap();
-        self.reset_indices();
+        self.conn.del(POPULATION_KEY)?;
+        self.conn.del(POP_UPDATES_KEY)?;
+        self.conn.del(PENDING_KEY)?;
+        for pattern in &["pending:*", "pending_world:*", "dormant:*", "grid:*", "space:*", "graph:*"] {
+            let keys = self.conn.keys(pattern)?;
+            self.conn.del_multiple(&keys)?;
+        }
+        self.reset_indices()
     }
 }
 
-/// Manages a simulation and coordinates a set of workers.
-pub struct Manager<S: Simulation, C: Redis> {
-    addr: String,
-    conn: Connection,
-    reporters: FnvHashMap<usize, Box<Fn(usize, &Population<S, C>, &Connection) -> () + Send>>,
-    pub population: Population<S, C>,
-    initial_pop: Vec<Vec<u8>>,
+/// The phases of a simulation step, in order.
+const PHASES: [&'static str; 3] = ["sync", "decide", "update"];
+
+/// How long the manager blocks waiting for workers to finish a phase
+/// before checking on them.
+const BARRIER_WAIT_MS: u64 = 250;
+
+/// How long the manager waits for workers to register before a run.
+const REGISTRATION_WAIT_MS: u64 = 250;
+
+/// Key for the list of requests to retire workers from the current run.
+const RETIRE_KEY: &'static str = "retire";
+
+/// How many slots the population is split into per worker, by default.
+/// Every slot's ownership is tracked and moved separately, so this is kept at one
+/// unless a run rebalances or takes in new workers (see `set_slots_per_worker`).
+const DEFAULT_SLOTS_PER_WORKER: usize = 1;
+
+/// Key for the list of failures reported by workers during a run.
+fn failures_key(run: &str) -> String {
+    format!("failures:{}", run)
 }
 
-impl<S: Simulation, C: Redis> Manager<S, C> {
-    pub fn new(addr: &str, conn: C, simulation: S) -> Manager<S, C> {
-        let population = Population::new(simulation, conn);
-        population.reset();
+/// Key for the list of reports from workers that finished a phase.
+fn finished_key(run: &str, seq: usize) -> String {
+    format!("finished:{}:{}", run, seq)
+}
 
-        // conn for commanding workers
-        let client = Client::open(addr).unwrap();
+/// Key for the load of a slot in the current step,
+/// as the number of agents and the time spent on them in microseconds.
+fn load_key(run: &str, slot: usize) -> String {
+    format!("load:{}:{}", run, slot)
+}
 
-        let m = Manager {
-            addr: addr.to_owned(),
-            population: population,
-            reporters: FnvHashMap::default(),
-            conn: client.get_connection().unwrap(),
-            initial_pop: Vec::new(),
-        };
-        m.reset();
-        m
-    }
+/// Microseconds in a duration.
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1000) as u64
+}
 
-    /// Reset the manager. This unregisters all workers and queues.
-    pub fn reset(&self) {
-        // reset sets
-        let _: () = self.conn.del("workers").unwrap();
-        let _: () = self.conn.del("finished").unwrap();
-    }
+/// Key for the slot a worker (by uid) is assigned for a run.
+fn worker_id_key(run: &str, uid: &str) -> String {
+    format!("worker_id:{}:{}", run, uid)
+}
 
-    /// Run the simulation for `n_steps`.
-    /// This will spawn the population across available workers
-    /// and begin sending them synchronized commands to step through the simulation.
-    pub fn run(&self, simulation: S, world: S::World, n_steps: usize) -> () {
-        let mut steps = 0;
-        let mut n_workers = 0;
-        while n_workers == 0 {
-            println!("Waiting for at least one worker...");
-            let wait = time::Duration::from_millis(1000);
-            thread::sleep(wait);
-            n_workers = self.n_workers();
-        }
-        println!("Ok, found {} workers.", n_workers);
-
-        // queue ids for workers to claim
-        let ids: Vec<usize> = (0..n_workers).collect();
-        let _: () = self.conn.del("worker_ids").unwrap();
-        let _: () = self.conn.lpush("worker_ids", ids).unwrap();
-        let hasher = WHasher::new(n_workers);
-        let mut population = self.population.clone();
-        population.hasher = Some(hasher.clone());
-        population.set_world(world);
+/// What a worker reports when it finishes a phase.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PhaseReport {
+    /// Uid of the worker.
+    pub uid: String,
 
-        // push initial population
-        let _: () = self.population
-            .conn
-            .sadd(POP_UPDATES_KEY, self.initial_pop.clone())
-            .unwrap();
+    /// The slots it ran the phase for.
+    pub slots: Vec<usize>,
 
-        // tell workers we're starting
-        let _: () = self.conn.publish("command", "start").unwrap();
+    /// The number of agents in those slots.
+    pub n_agents: usize,
 
-        let mut queued_updates = Updates::new(hasher.clone());
-        while steps < n_steps {
-            population.update();
-            let _: () = self.conn.publish("command", "sync").unwrap();
-            self.wait_until_finished();
-            let _: () = self.conn.del("finished").unwrap();
+    /// How long the phase took it.
+    pub duration: Duration,
+}
 
-            // run any registered reporters, if appropriate
-            for (interval, reporter) in &self.reporters {
-                if steps % interval == 0 {
-                    reporter(steps, &population, &self.conn);
-                }
-            }
+/// The manager's view of the workers taking part in a run.
+struct Coordinator {
+    /// Id of the run.
+    run: String,
 
-            let _: () = self.conn.publish("command", "decide").unwrap();
-            self.wait_until_finished();
-            let _: () = self.conn.del("finished").unwrap();
+    /// Sequence number of the current phase.
+    seq: usize,
 
+    /// The current step.
+    step: usize,
 
-            // TODO move this to a worker?
-            let world = population.world();
-            {
-                simulation.world_decide(&world, &population, &mut queued_updates);
-                queued_updates.push(&population);
-            }
+    /// The current stage of the step (see `Schedule::Staged`).
+    stage: usize,
 
-            let _: () = self.conn.publish("command", "update").unwrap();
-            self.wait_until_finished();
-            let _: () = self.conn.del("finished").unwrap();
+    /// Number of worker slots (partitions of the population).
+    n_slots: usize,
 
-            // update world
-            // TODO move this to a worker?
-            {
-                let mut datas =
-                    self.conn.smembers::<&str, Vec<Vec<u8>>>(WORLD_UPDATES_KEY).unwrap();
-                let _: () = self.conn.del(WORLD_UPDATES_KEY).unwrap();
+    /// Uids of the live workers in the run.
+    members: Vec<String>,
 
-                let updates: Vec<S::Update> =
-                    datas.drain(..).map(|data| decode(data).unwrap()).collect();
-                let world = simulation.world_update(world, updates);
-                population.set_world(world);
-            }
+    /// Which worker (by uid) owns each slot.
+    owners: FnvHashMap<usize, String>,
 
-            steps += 1;
+    /// The id to give the next worker to join.
+    next_id: usize,
+
+    monitor: HeartbeatMonitor,
+}
+
+impl Coordinator {
+    /// How well moving a slot to a worker (by uid) keeps slot blocks contiguous:
+    /// 0 if it's next to one of that worker's slots, 1 if it's at the edge
+    /// of its current owner's block, and 2 if it's in the middle of it.
+    fn boundary_rank(&self, slot: usize, to: &str) -> usize {
+        let owner = &self.owners[&slot];
+        let neighbours: Vec<Option<&String>> = vec![slot.checked_sub(1).and_then(|prev| self.owners.get(&prev)),
+                                                     self.owners.get(&(slot + 1))];
+        if neighbours.iter().any(|n| n.map_or(false, |n| n == to)) {
+            0
+        } else if neighbours.iter().any(|n| n.map_or(true, |n| n != owner)) {
+            1
+        } else {
+            2
         }
+    }
+}
+
+/// Manages a simulation and coordinates a set of workers.
+pub struct Manager<S: Simulation, B: Backend> {
+    conn: B,
+    reporters: FnvHashMap<usize, Box<Fn(usize, &Population<S, B>, &B) -> () + Send>>,
+    phase_reporters: Vec<Box<Fn(usize, &str, &[PhaseReport]) -> () + Send>>,
+    pub population: Population<S, B>,
+    initial_pop: Vec<Vec<u8>>,
+    heartbeat_timeout: Duration,
+    phase_timeout: Option<Duration>,
+    failure_policy: FailurePolicy,
+    checkpoints: Option<(usize, PathBuf)>,
+    restored: Option<Checkpoint>,
+    seed: u64,
+    n_spawned: u64,
+    stop_conditions: Vec<(String, StopCondition<S, B>)>,
+    recorders: Vec<(usize, Recorder<S, B>)>,
+    snapshots: Option<(usize, PathBuf, RecordFormat)>,
+    record_output: Option<(PathBuf, RecordFormat)>,
+    recording: Mutex<Recording>,
+    slots_per_worker: usize,
+    rebalancing: Option<(usize, f64)>,
+    verbose: bool,
+}
 
-        println!("done. terminating workers");
-        let _: () = self.conn.publish("command", "terminate").unwrap();
+impl<S: Simulation, B: Backend> Manager<S, B> {
+    pub fn new(conn: B, simulation: S) -> Result<Manager<S, B>> {
+        let population = Population::new(simulation, conn.clone());
+        population.reset()?;
+
+        let m = Manager {
+            population: population,
+            reporters: FnvHashMap::default(),
+            phase_reporters: Vec::new(),
+            conn: conn,
+            initial_pop: Vec::new(),
+            heartbeat_timeout: Duration::from_secs(10),
+            phase_timeout: None,
+            failure_policy: FailurePolicy::Abort,
+            checkpoints: None,
+            restored: None,
+            seed: rand::random(),
+            n_spawned: 0,
+            stop_conditions: Vec::new(),
+            recorders: Vec::new(),
+            snapshots: None,
+            record_output: None,
+            recording: Mutex::new(Recording::new()),
+            slots_per_worker: DEFAULT_SLOTS_PER_WORKER,
+            rebalancing: None,
+            verbose: false,
+        };
+        m.reset()?;
+        Ok(m)
     }
 
-    /// Register a reporter function to be called every `n_steps`.
+    /// Create a manager which continues the run saved in a checkpoint file.
     ///
-    /// It receives a `Population` which can be used to query agents,
-    /// compute aggregate statistics, etc, and a Redis connection
-    /// that can be used, for example, to send reports via pubsub.
-    pub fn register_reporter<F>(&mut self, n_steps: usize, func: F) -> ()
-        where F: Fn(usize, &Population<S, C>, &Connection) -> () + Send + 'static
-    {
-        self.reporters.insert(n_steps, Box::new(func));
+    /// This restores the world, agents, indices, and queued updates to the backend.
+    /// The world can then be fetched with `manager.population.world()` to pass to `run`,
+    /// which continues from the checkpoint's step, i.e. `n_steps` is the total number of steps
+    /// including those run before the checkpoint.
+    pub fn resume_from<P: AsRef<Path>>(conn: B, simulation: S, path: P) -> Result<Manager<S, B>> {
+        let mut m = Manager::new(conn, simulation)?;
+        let checkpoint = Checkpoint::load(path)?;
+
+        // clear out updates left over from previous runs
+        let keys = m.conn.keys("updates:*")?;
+        m.conn.del_multiple(&keys)?;
+
+        m.conn.set("world", checkpoint.world.clone())?;
+        let agents: Vec<(String, Vec<u8>)> = checkpoint.agents
+            .iter()
+            .map(|&(id, ref data)| (id.to_string(), data.clone()))
+            .collect();
+        m.conn.set_multiple(&agents)?;
+        let ids = checkpoint.agents.iter().map(|&(id, _)| member(id)).collect();
+        m.conn.sadd(POPULATION_KEY, ids)?;
+        for &(ref name, ref ids) in &checkpoint.indices {
+            m.population.indexes(name, ids.clone())?;
+        }
+        m.conn.sadd(POP_UPDATES_KEY, checkpoint.pop_updates.clone())?;
+        m.conn.sadd(WORLD_UPDATES_KEY, checkpoint.world_updates.clone())?;
+        for &(ref key, ref members) in &checkpoint.sets {
+            m.conn.sadd(key, members.clone())?;
+        }
+        m.conn.set_multiple(&checkpoint.values)?;
+        for &(step, ref data) in &checkpoint.pending_world_updates {
+            m.conn.lpush(&pending_world_key(step), vec![data.clone()])?;
+        }
+        // older checkpoints only have the steps delayed updates are due at
+        let due = checkpoint.pending_updates
+            .iter()
+            .chain(checkpoint.pending_world_updates.iter())
+            .map(|&(step, _)| step)
+            .chain(checkpoint.pending.iter().cloned())
+            .map(member)
+            .collect();
+        m.conn.sadd(PENDING_KEY, due)?;
+
+        m.seed = checkpoint.seed;
+        m.restored = Some(checkpoint);
+        Ok(m)
     }
 
-    fn wait_until_finished(&self) {
-        while self.conn.scard::<&str, usize>("finished").unwrap() != self.n_workers() {
+    /// Reset the manager. This unregisters all workers and queues.
+    /// Workers that are still up re-register when the next run starts.
+    pub fn reset(&self) -> Result<()> {
+        // reset sets
+        self.conn.del("workers")?;
+        for pattern in &["finished:*", "failures:*", "worker_id:*", "load:*"] {
+            let keys = self.conn.keys(pattern)?;
+            self.conn.del_multiple(&keys)?;
         }
+        Ok(())
     }
 
-    /// Spawn an agent.
-    pub fn spawn(&mut self, state: S::State) -> u64 {
-        let id = hash(&Uuid::new_v4().to_string());
-        let update = PopulationUpdate::Spawn(id, state);
-        let data = encode(update).unwrap();
-        self.initial_pop.push(data);
-        id
+    /// Set how long a worker can go without sending a heartbeat before it's considered dead.
+    /// Defaults to 10 seconds.
+    pub fn set_heartbeat_timeout(&mut self, timeout: Duration) {
+        self.heartbeat_timeout = timeout;
     }
 
-    /// Spawn multiple agents.
-    pub fn spawns(&mut self, mut states: Vec<S::State>) -> Vec<u64> {
-        states.drain(..).map(|s| self.spawn(s)).collect()
+    /// Set how long a worker can take on a single phase before it's considered stuck.
+    /// By default there is no limit.
+    pub fn set_phase_timeout(&mut self, timeout: Duration) {
+        self.phase_timeout = Some(timeout);
     }
 
-    /// Get the number of workers.
-    pub fn n_workers(&self) -> usize {
-        self.conn.scard::<&str, usize>("workers").unwrap()
+    /// Set what to do when a worker dies or gets stuck.
+    /// Defaults to `FailurePolicy::Abort`.
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
     }
-}
-
-/// A process that computes and applies updates for a local population of agents.
-pub struct Worker<S: Simulation, C: Redis> {
-    id: usize,
-    uid: Uuid,
-    manager: Connection,
-    population: Population<S, C>,
-    local: FnvHashMap<u64, Agent<S::State>>,
-    updates: FnvHashMap<u64, Vec<S::Update>>,
-    pubsub: PubSub,
-    simulation: S,
-    queued_updates: Updates<S>,
-}
 
-impl<S: Simulation, C: Redis> Worker<S, C> {
-    pub fn new(addr: &str, conn: C, simulation: S) -> Worker<S, C> {
-        let client = Client::open(addr).unwrap();
-        let hasher = WHasher::new(0);
+    /// Split the population into this many slots per worker. Defaults to 1.
+    /// Slots are the unit agents are moved between workers in,
+    /// when rebalancing, when workers join or retire, or when a worker fails,
+    /// and a run can't grow to more workers than it has slots.
+    ///
+    /// Raise this (e.g. to 8) for runs that rebalance or that workers may join,
+    /// as workers that join a run with no spare slots are left without agents.
+    /// More slots mean finer moves, but more ownership bookkeeping each step.
+    pub fn set_slots_per_worker(&mut self, n_slots: usize) {
+        self.slots_per_worker = cmp::max(n_slots, 1);
+    }
+
+    /// Every `n_steps`, check how much time workers spend on their agents,
+    /// and if the busiest is more than `threshold` (e.g. 0.2 for 20%) over the average,
+    /// move slots from busier workers to less busy ones.
+    ///
+    /// Agents are moved a slot at a time, see `set_slots_per_worker`.
+    pub fn set_rebalancing(&mut self, n_steps: usize, threshold: f64) {
+        self.rebalancing = Some((n_steps, threshold));
+    }
+
+    /// Print progress, e.g. when workers join or fail, to stdout.
+    /// Off by default.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    fn log(&self, msg: &str) {
+        if self.verbose {
+            println!("{}", msg);
+        }
+    }
+
+    /// Set the run's seed, from which all randomness provided by djinn
+    /// (e.g. `Updates::rng`) and all agent ids are derived, so that runs with
+    /// the same seed are identical. This should be set before spawning any agents.
+    ///
+    /// By default a random seed is used.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Get the run's seed, e.g. to log it so the run can be reproduced.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Save a checkpoint to `path` every `n_steps`, replacing the previous one.
+    /// See `Manager::resume_from` for resuming from it.
+    pub fn set_checkpoints<P: AsRef<Path>>(&mut self, n_steps: usize, path: P) {
+        self.checkpoints = Some((n_steps, path.as_ref().to_path_buf()));
+    }
+
+    /// Snapshot the run, which is at the start of `step`.
+    fn checkpoint(&self, step: usize) -> Result<Checkpoint> {
+        let world = match self.conn.get("world")? {
+            Some(world) => world,
+            None => return Err(DjinnError::Protocol("the world has not been set".to_owned())),
+        };
+
+        let ids: Vec<u64> = from_members(self.conn.smembers(POPULATION_KEY)?)?;
+        let keys: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let agents = ids.into_iter()
+            .zip(self.conn.get_multiple(&keys)?)
+            .map(|(id, data)| match data {
+                Some(data) => Ok((id, data)),
+                None => Err(DjinnError::MissingAgent(id)),
+            })
+            .collect::<Result<Vec<(u64, Vec<u8>)>>>()?;
+
+        let mut indices = Vec::new();
+        for key in self.conn.keys("idx:*")? {
+            let ids = from_members(self.conn.smembers(&key)?)?;
+            indices.push((key["idx:".len()..].to_owned(), ids));
+        }
+
+        // agent update queues are the `updates:{worker id}` lists
+        let mut agent_updates = Vec::new();
+        for key in self.conn.keys("updates:*")? {
+            if key["updates:".len()..].parse::<usize>().is_ok() {
+                agent_updates.extend(self.conn.lrange(&key)?);
+            }
+        }
+
+        // delayed updates are the `pending:{step}:{worker id}` lists
+        let mut pending_updates = Vec::new();
+        for key in self.conn.keys("pending:*")? {
+            let due = key["pending:".len()..].splitn(2, ':').next().and_then(|step| step.parse().ok());
+            if let Some(due) = due {
+                pending_updates.extend(self.conn.lrange(&key)?.into_iter().map(|data| (due, data)));
+            }
+        }
+        let mut sets = Vec::new();
+        for pattern in &STRUCTURE_SET_KEYS {
+            for key in self.conn.keys(pattern)? {
+                let members = self.conn.smembers(&key)?;
+                sets.push((key, members));
+            }
+        }
+        let mut values = Vec::new();
+        for pattern in &STRUCTURE_VALUE_KEYS {
+            let keys = self.conn.keys(pattern)?;
+            for (key, val) in keys.iter().zip(self.conn.get_multiple(&keys)?) {
+                if let Some(val) = val {
+                    values.push((key.clone(), val));
+                }
+            }
+        }
+
+        let mut dormant = Vec::new();
+        let keys = self.conn.keys("dormant:*")?;
+        for data in self.conn.get_multiple(&keys)? {
+            if let Some(data) = data {
+                let sleeping: Vec<(u64, usize, Option<usize>)> = decode(data)?;
+                dormant.extend(sleeping);
+            }
+        }
+
+        let mut pending_world_updates = Vec::new();
+        for key in self.conn.keys("pending_world:*")? {
+            if let Ok(due) = key["pending_world:".len()..].parse() {
+                pending_world_updates.extend(self.conn.lrange(&key)?.into_iter().map(|data| (due, data)));
+            }
+        }
+
+        Ok(Checkpoint {
+            step: step,
+            seed: self.seed,
+            world: world,
+            agents: agents,
+            indices: indices,
+            pop_updates: self.conn.smembers(POP_UPDATES_KEY)?,
+            world_updates: self.conn.smembers(WORLD_UPDATES_KEY)?,
+            agent_updates: agent_updates,
+            pending_updates: pending_updates,
+            pending_world_updates: pending_world_updates,
+            pending: from_members(self.conn.smembers(PENDING_KEY)?)?,
+            dormant: dormant,
+            sets: sets,
+            values: values,
+        })
+    }
+
+    /// Hand restored agents and their queued updates over to the workers.
+    fn distribute(&self, checkpoint: &Checkpoint, hasher: &WHasher) -> Result<()> {
+        // these go straight to the workers rather than being respawned,
+        // so `on_spawns` isn't called for them again
+        let mut targets: FnvHashMap<usize, Vec<Vec<u8>>> = FnvHashMap::default();
+        for &(id, ref data) in &checkpoint.agents {
+            let state = self.population.migrations.decode(data)?;
+            let agent = Agent {
+                id: id,
+                state: state,
+            };
+            targets.entry(hasher.hash(id)).or_insert_with(Vec::new).push(S::Codec::encode(&agent)?);
+        }
+        for (worker_id, agents) in targets {
+            self.conn.lpush(&format!("spawn:{}", worker_id), agents)?;
+        }
+
+        // the number of workers may have changed,
+        // so re-route queued updates
+        let mut targets: FnvHashMap<usize, Vec<Vec<u8>>> = FnvHashMap::default();
+        for data in &checkpoint.agent_updates {
+            let (id, _, _): (u64, Stamp, S::Update) = S::Codec::decode(&data)?;
+            targets.entry(hasher.hash(id)).or_insert_with(Vec::new).push(data.clone());
+        }
+        for (worker_id, updates) in targets {
+            self.conn.lpush(&format!("updates:{}", worker_id), updates)?;
+        }
+        let mut targets: FnvHashMap<(usize, usize), Vec<Vec<u8>>> = FnvHashMap::default();
+        for &(due, ref data) in &checkpoint.pending_updates {
+            let (id, _, _): (u64, Stamp, S::Update) = S::Codec::decode(&data)?;
+            targets.entry((due, hasher.hash(id))).or_insert_with(Vec::new).push(data.clone());
+        }
+        for ((due, worker_id), updates) in targets {
+            self.conn.lpush(&pending_key(due, worker_id), updates)?;
+        }
+
+        // as are sleeping agents
+        let mut targets: FnvHashMap<usize, Vec<(u64, usize, Option<usize>)>> = FnvHashMap::default();
+        for &(id, since, wake) in &checkpoint.dormant {
+            targets.entry(hasher.hash(id)).or_insert_with(Vec::new).push((id, since, wake));
+        }
+        let dormant = targets.into_iter()
+            .map(|(slot, sleeping)| Ok((dormant_key(slot), encode(sleeping)?)))
+            .collect::<Result<Vec<(String, Vec<u8>)>>>()?;
+        self.conn.set_multiple(&dormant)
+    }
+
+    /// Register a condition, checked after each step, which ends the run early when met.
+    ///
+    /// It receives the number of steps run so far, the world,
+    /// and a `Population` which can be used to query agents.
+    /// The `name` is reported in the `RunSummary` if the condition ends the run.
+    pub fn register_stop_condition<F>(&mut self, name: &str, func: F) -> ()
+        where F: Fn(usize, &S::World, &Population<S, B>) -> Result<bool> + Send + 'static
+    {
+        self.stop_conditions.push((name.to_owned(), StopCondition::Custom(Box::new(func))));
+    }
+
+    /// End the run early if the population dies out.
+    pub fn stop_on_extinction(&mut self) {
+        self.stop_conditions.push(("extinction".to_owned(), StopCondition::Extinction));
+    }
+
+    /// End the run early if the world hasn't changed for `n_steps`.
+    pub fn stop_on_convergence(&mut self, n_steps: usize) {
+        self.stop_conditions.push(("convergence".to_owned(), StopCondition::Convergence(n_steps)));
+    }
+
+    /// End the run early (after the current step) once it has run for longer than `limit`.
+    pub fn set_time_limit(&mut self, limit: Duration) {
+        self.stop_conditions.push(("time limit".to_owned(), StopCondition::TimeLimit(limit)));
+    }
+
+    /// Check the stop conditions, returning the name of the first one that is met, if any.
+    fn check_stop(&self,
+                  step: usize,
+                  world: &S::World,
+                  population: &Population<S, B>,
+                  progress: &RunProgress)
+                  -> Result<Option<String>> {
+        for &(ref name, ref condition) in &self.stop_conditions {
+            if condition.is_met(step, world, population, progress)? {
+                return Ok(Some(name.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run the simulation for `n_steps`, or until a stop condition is met.
+    /// This will spawn the population across available workers
+    /// and begin sending them synchronized commands to step through the simulation.
+    ///
+    /// If a worker fails, the run is aborted and the worker's error is returned.
+    /// If a worker dies or gets stuck, what happens depends on the `FailurePolicy`.
+    ///
+    /// Workers stay up after the run, so they can be used for further runs
+    /// (e.g. by a new `Manager`), until the manager `shutdown`s them.
+    pub fn run(&self, simulation: S, world: S::World, n_steps: usize) -> Result<RunSummary> {
+        let run = Uuid::new_v4().to_string();
+        let res = self.run_steps(&run, simulation, world, n_steps);
+
+        // end the run even if it failed,
+        // so workers don't wait on us forever
+        self.log("done. ending run");
+        let ended = self.conn
+            .publish("command", &format!("end_run {}", run))
+            .and_then(|_| self.conn.keys(&worker_id_key(&run, "*")))
+            .and_then(|keys| self.conn.del_multiple(&keys))
+            .and_then(|_| self.conn.keys(&format!("load:{}:*", run)))
+            .and_then(|keys| self.conn.del_multiple(&keys))
+            .and_then(|_| self.conn.keys("dormant:*"))
+            .and_then(|keys| self.conn.del_multiple(&keys));
+        let summary = res?;
+        ended.map(|_| summary)
+    }
+
+    /// Shut down all workers listening to this manager.
+    pub fn shutdown(&self) -> Result<()> {
+        shutdown_workers(&self.conn)
+    }
+
+    fn run_steps(&self,
+                 run: &str,
+                 simulation: S,
+                 world: S::World,
+                 n_steps: usize)
+                 -> Result<RunSummary> {
+        let mut steps = match self.restored {
+            Some(ref checkpoint) => checkpoint.step,
+            None => 0,
+        };
+
+        // ask idle workers to (re-)register,
+        // which also clears out any that have gone away
+        self.conn.del("workers")?;
+        self.conn.del(RETIRE_KEY)?;
+        self.log("Waiting for at least one worker...");
+        let mut n_workers = 0;
+        while n_workers == 0 {
+            self.conn.publish("command", "register")?;
+            thread::sleep(Duration::from_millis(REGISTRATION_WAIT_MS));
+            n_workers = self.n_workers()?;
+        }
+        let mut members: Vec<String> = from_members(self.conn.smembers("workers")?)?;
+        members.sort();
+        let n_workers = members.len();
+        self.log(&format!("Ok, found {} workers.", n_workers));
+
+        // assign workers their ids
+        let ids: Vec<(String, Vec<u8>)> = members.iter()
+            .enumerate()
+            .map(|(id, uid)| (worker_id_key(run, uid), member(id)))
+            .collect();
+        self.conn.set_multiple(&ids)?;
+
+        // the partitioner lays out which worker owns each slot,
+        // so neighbouring agents (e.g. with a range or region partitioner) stay together
+        let n_slots = n_workers * self.slots_per_worker;
+        let partitioner = simulation.partitioner();
+        let assignment = partitioner.assign(n_slots, n_workers);
+        let hasher = WHasher::new(n_slots, partitioner);
+        let mut population = self.population.clone();
+        population.hasher = Some(hasher.clone());
+        population.set_world(world)?;
+
+        let mut coord = Coordinator {
+            run: run.to_owned(),
+            seq: 0,
+            step: steps,
+            stage: 0,
+            n_slots: n_slots,
+            owners: (0..n_slots).map(|slot| (slot, members[assignment[slot]].clone())).collect(),
+            members: members,
+            next_id: n_workers,
+            monitor: HeartbeatMonitor::new(self.heartbeat_timeout),
+        };
+
+        // share the seed with workers
+        self.conn.set(SEED_KEY, member(self.seed))?;
+
+        // start a fresh recording,
+        // continuing the output file if resuming
+        {
+            let mut recording = self.recording.lock()?;
+            *recording = Recording::new();
+            if let Some((ref path, format)) = self.record_output {
+                recording.stream_to(path, format, self.restored.is_some())?;
+            }
+        }
+
+        // push initial population
+        self.population.conn.sadd(POP_UPDATES_KEY, self.initial_pop.clone())?;
+        if let Some(ref checkpoint) = self.restored {
+            self.distribute(checkpoint, &hasher)?;
+        }
+
+        // tell workers we're starting
+        self.conn.publish("command", &format!("start {} {} {}", run, n_slots, n_workers))?;
+
+        let schedule = simulation.schedule();
+        let event_driven = schedule == Schedule::DiscreteEvent;
+        let mut queued_updates = Updates::new(hasher.clone());
+        queued_updates.seed = self.seed;
+        queued_updates.schedule = schedule;
+        let mut progress = RunProgress::new();
+        let converging = self.stop_conditions.iter().any(|&(_, ref condition)| match *condition {
+            StopCondition::Convergence(_) => true,
+            _ => false,
+        });
+        population.update()?;
+        while steps < n_steps {
+            coord.step = steps;
+            self.phase("sync", &mut coord)?;
+
+            // run any registered reporters, if appropriate
+            for (interval, reporter) in &self.reporters {
+                if steps % interval == 0 {
+                    reporter(steps, &population, &self.conn);
+                }
+            }
+            self.record(steps, &population)?;
+            self.snapshot(steps, &population)?;
+
+            // with staged activation, each stage's updates
+            // are applied before the next stage decides
+            let n_stages = schedule.n_stages();
+            for stage in 0..n_stages {
+                coord.stage = stage;
+                self.phase("decide", &mut coord)?;
+                if stage + 1 < n_stages {
+                    self.phase("update", &mut coord)?;
+                }
+            }
+
+            // TODO move this to a worker?
+            let world = population.world()?;
+            let world_ops: Vec<BackendOp>;
+            {
+                queued_updates.begin(steps, WORLD_SENDER);
+                population.begin_draws(queued_updates.key);
+                simulation.world_decide(&world, &population, &mut queued_updates);
+                population.end_draws();
+                world_ops = queued_updates.ops.drain(..).collect();
+                queued_updates.push(&population)?;
+            }
+
+            self.phase("update", &mut coord)?;
+            self.conn.apply(world_ops)?;
+            self.conn.srem(PENDING_KEY, vec![member(steps)])?;
+
+            // apply this step's spawns and kills before checking stop conditions,
+            // so e.g. extinction is caught at the step it happens
+            let pop_changed = self.conn.scard(POP_UPDATES_KEY)? > 0;
+            population.update()?;
+
+            // update world
+            // TODO move this to a worker?
+            let stopped;
+            {
+                // sort so that updates are applied in a consistent order
+                let mut datas = self.conn.smembers(WORLD_UPDATES_KEY)?;
+                self.conn.del(WORLD_UPDATES_KEY)?;
+                let key = pending_world_key(steps);
+                datas.extend(self.conn.lrange(&key)?);
+                self.conn.del(&key)?;
+                datas.sort();
+
+                let updates: Vec<S::Update> = decode_all::<S::Codec, _>(datas)?;
+                let prev = if converging { Some(world.clone()) } else { None };
+                let world = simulation.world_update(world, updates);
+                progress.world_changed(prev.as_ref() != Some(&world));
+                stopped = self.check_stop(steps + 1, &world, &population, &progress)?;
+                population.set_world(world)?;
+            }
+
+            // in discrete-event mode, skip ahead to
+            // the next step that something is due at
+            let next = if event_driven {
+                self.next_event(steps, pop_changed)?
+            } else {
+                Some(steps + 1)
+            };
+            let prev = steps;
+            steps = next.map_or(steps + 1, |next| cmp::min(next, n_steps));
+
+            if let Some((interval, ref path)) = self.checkpoints {
+                if steps / interval > prev / interval {
+                    self.checkpoint(steps)?.save(path)?;
+                }
+            }
+
+            if stopped.is_none() && next.is_some() {
+                self.resize(&mut coord)?;
+                if let Some((interval, threshold)) = self.rebalancing {
+                    if steps / interval > prev / interval {
+                        self.rebalance(threshold, &mut coord)?;
+                    }
+                }
+            }
+
+            if let Some(name) = stopped {
+                self.log(&format!("Stopping at step {}: {}", steps, name));
+                return Ok(RunSummary {
+                    step: steps,
+                    reason: StopReason::Condition(name),
+                });
+            }
+            if next.is_none() {
+                self.log(&format!("Stopping at step {}: no more events", steps));
+                return Ok(RunSummary {
+                    step: steps,
+                    reason: StopReason::NoEvents,
+                });
+            }
+        }
+        Ok(RunSummary {
+            step: steps,
+            reason: StopReason::StepLimit,
+        })
+    }
+
+    /// The first step after `step` that something is due at, in discrete-event mode:
+    /// new agents (if the population just changed), delayed updates, or agents woken by updates or waking up on their own.
+    fn next_event(&self, step: usize, pop_changed: bool) -> Result<Option<usize>> {
+        if pop_changed {
+            return Ok(Some(step + 1));
+        }
+        let due: Vec<usize> = from_members(self.conn.smembers(PENDING_KEY)?)?;
+        Ok(due.into_iter().filter(|&due| due > step).min())
+    }
+
+    /// If the busiest worker's load is more than `threshold` over the average,
+    /// move slots from the busiest workers to the least busy ones,
+    /// and wait for workers to hand them over.
+    ///
+    /// Slots at the edges of the busiest worker's blocks are moved first,
+    /// preferably ones next to the least busy worker's,
+    /// so that workers keep contiguous ranges of slots.
+    ///
+    /// A slot's load is the time its worker spent on it in the last step,
+    /// or its number of agents, if no time was measured.
+    fn rebalance(&self, threshold: f64, coord: &mut Coordinator) -> Result<()> {
+        let keys: Vec<String> = (0..coord.n_slots).map(|slot| load_key(&coord.run, slot)).collect();
+        let mut counts = Vec::with_capacity(keys.len());
+        let mut times = Vec::with_capacity(keys.len());
+        for data in self.conn.get_multiple(&keys)? {
+            let load = match data {
+                Some(data) => from_member::<String>(data)?,
+                None => continue,
+            };
+            let mut parts = load.split(' ').map(|part| part.parse::<u64>().unwrap_or(0));
+            counts.push(parts.next().unwrap_or(0) as f64);
+            times.push(parts.next().unwrap_or(0) as f64);
+        }
+        if counts.len() < coord.n_slots {
+            // not every slot has reported yet
+            return Ok(());
+        }
+        let loads = if times.iter().all(|&t| t > 0.) { times } else { counts };
+
+        let mut worker_loads: FnvHashMap<String, f64> =
+            coord.members.iter().map(|uid| (uid.clone(), 0.)).collect();
+        for (slot, owner) in &coord.owners {
+            *worker_loads.entry(owner.clone()).or_insert(0.) += loads[*slot];
+        }
+        let mean = loads.iter().sum::<f64>() / worker_loads.len() as f64;
+
+        // greedily move the slot that best evens out the busiest and least busy workers,
+        // moving each slot at most once
+        let mut moves: Vec<(usize, String)> = Vec::new();
+        loop {
+            let mut by_load: Vec<(&String, &f64)> = worker_loads.iter().collect();
+            by_load.sort_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(cmp::Ordering::Equal).then(a.0.cmp(b.0)));
+            let (idlest, min) = (by_load[0].0.clone(), *by_load[0].1);
+            let (busiest, max) = match by_load.last() {
+                Some(&(uid, load)) => (uid.clone(), *load),
+                None => break,
+            };
+            if max <= mean * (1. + threshold) || moves.iter().any(|m| m.1 == busiest) {
+                // don't move slots back off a worker that was just given some
+                break;
+            }
+            let gap = max - min;
+            let best = coord.owners
+                .iter()
+                .filter(|&(slot, owner)| *owner == busiest && !moves.iter().any(|m| m.0 == *slot))
+                .map(|(slot, _)| *slot)
+                .filter(|slot| loads[*slot] > 0. && loads[*slot] < gap)
+                .min_by_key(|slot| {
+                    (coord.boundary_rank(*slot, &idlest),
+                     ((loads[*slot] - gap / 2.).abs() * 1000.) as u64)
+                });
+            let slot = match best {
+                Some(slot) => slot,
+                None => break,
+            };
+            *worker_loads.get_mut(&busiest).unwrap() -= loads[slot];
+            *worker_loads.get_mut(&idlest).unwrap() += loads[slot];
+            coord.owners.insert(slot, idlest.clone());
+            moves.push((slot, idlest));
+        }
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        self.log(&format!("Rebalancing: moving {} slot(s)", moves.len()));
+        self.migrate(moves, coord)
+    }
+
+    /// Let in workers that registered since the run started,
+    /// and let go of those asked to retire (see `retire_workers`),
+    /// evening out how many slots each worker has.
+    fn resize(&self, coord: &mut Coordinator) -> Result<()> {
+        let mut joined: Vec<String> = from_members::<String>(self.conn.smembers("workers")?)?
+            .into_iter()
+            .filter(|uid| !coord.members.contains(uid))
+            .collect();
+        joined.sort();
+
+        let mut n_retiring = 0;
+        while let Some(data) = self.conn.lpop(RETIRE_KEY)? {
+            n_retiring += from_member::<usize>(data)?;
+        }
+        if joined.is_empty() && n_retiring == 0 {
+            return Ok(());
+        }
+
+        for uid in &joined {
+            self.log(&format!("Worker {} joined", uid));
+            self.conn.set(&worker_id_key(&coord.run, uid), member(coord.next_id))?;
+            coord.next_id += 1;
+            self.conn.publish("command", &format!("join {} {} {}", coord.run, uid, coord.n_slots))?;
+            coord.members.push(uid.clone());
+        }
+
+        // retire the most recently joined workers, keeping at least one
+        let n_retiring = cmp::min(n_retiring, coord.members.len() - 1);
+        let start = coord.members.len() - n_retiring;
+        let retired: Vec<String> = coord.members.drain(start..).collect();
+
+        // hand out the retired workers' slots, then even out the rest,
+        // by moving slots from the workers with the most to those with the fewest
+        let mut moves: Vec<(usize, String)> = Vec::new();
+        let mut slots: Vec<usize> = coord.owners.keys().cloned().collect();
+        slots.sort();
+        for slot in slots {
+            if retired.contains(&coord.owners[&slot]) {
+                let uid = self.fewest_slots(coord, &coord.members);
+                coord.owners.insert(slot, uid.clone());
+                moves.push((slot, uid));
+            }
+        }
+        loop {
+            let most = coord.members
+                .iter()
+                .max_by_key(|uid| (coord.owners.values().filter(|o| o == uid).count(), uid.as_str()))
+                .unwrap()
+                .clone();
+            let fewest = self.fewest_slots(coord, &coord.members);
+            let count = |uid: &String| coord.owners.values().filter(|o| *o == uid).count();
+            if count(&most) <= count(&fewest) + 1 {
+                break;
+            }
+            let slot = coord.owners
+                .iter()
+                .filter(|&(_, owner)| *owner == most)
+                .map(|(slot, _)| *slot)
+                .min_by_key(|slot| (coord.boundary_rank(*slot, &fewest), cmp::Reverse(*slot)))
+                .unwrap();
+            coord.owners.insert(slot, fewest.clone());
+            moves.push((slot, fewest));
+        }
+        if !moves.is_empty() {
+            self.migrate(moves, coord)?;
+        }
+        for uid in &joined {
+            if !coord.owners.values().any(|owner| owner == uid) {
+                self.log(&format!("Worker {} has no slots to take on, see `set_slots_per_worker`", uid));
+            }
+        }
+
+        for uid in retired {
+            self.log(&format!("Worker {} retired", uid));
+            self.conn.publish("command", &format!("retire {} {}", coord.run, uid))?;
+            self.conn.srem("workers", vec![member(&uid)])?;
+            self.conn.del(&worker_id_key(&coord.run, &uid))?;
+            coord.monitor.forget(&uid);
+        }
+        Ok(())
+    }
+
+    /// The worker (by uid) with the fewest slots, breaking ties by uid.
+    fn fewest_slots(&self, coord: &Coordinator, uids: &[String]) -> String {
+        uids.iter()
+            .min_by_key(|uid| (coord.owners.values().filter(|o| o == uid).count(), uid.as_str()))
+            .unwrap()
+            .clone()
+    }
+
+    /// Tell workers which slots (already assigned in `coord`) are moving to which workers,
+    /// and wait for them to hand them over.
+    fn migrate(&self, moves: Vec<(usize, String)>, coord: &mut Coordinator) -> Result<()> {
+        let moves: Vec<String> = moves.into_iter().map(|(slot, uid)| format!("{}:{}", slot, uid)).collect();
+        coord.seq += 1;
+        self.conn.publish("command",
+                          &format!("migrate {} {} {} {}", coord.seq, coord.step, coord.stage, moves.join(",")))?;
+        self.wait_until_finished("migrate", coord)?;
+        self.conn.del(&finished_key(&coord.run, coord.seq))
+    }
+
+    /// Tell workers to run a phase and wait for them to finish it.
+    fn phase(&self, phase: &str, coord: &mut Coordinator) -> Result<()> {
+        coord.seq += 1;
+        self.conn.publish("command",
+                          &format!("{} {} {} {}", phase, coord.seq, coord.step, coord.stage))?;
+        let reports = self.wait_until_finished(phase, coord)?;
+        for reporter in &self.phase_reporters {
+            reporter(coord.step, phase, &reports);
+        }
+        self.conn.del(&finished_key(&coord.run, coord.seq))
+    }
+
+    /// Register a reporter function to be called every `n_steps`.
+    ///
+    /// It receives a `Population` which can be used to query agents,
+    /// compute aggregate statistics, etc, and a backend connection
+    /// that can be used, for example, to send reports via pubsub.
+    pub fn register_reporter<F>(&mut self, n_steps: usize, func: F) -> ()
+        where F: Fn(usize, &Population<S, B>, &B) -> () + Send + 'static
+    {
+        self.reporters.insert(n_steps, Box::new(func));
+    }
+
+    /// Register a function to be called after each phase of each step,
+    /// with the step, the phase (e.g. "decide"), and what each worker reported
+    /// when it finished it, e.g. to see how long phases take and which workers are slowest.
+    pub fn register_phase_reporter<F>(&mut self, func: F) -> ()
+        where F: Fn(usize, &str, &[PhaseReport]) -> () + Send + 'static
+    {
+        self.phase_reporters.push(Box::new(func));
+    }
+
+    /// Register a recorder function to be called every `n_steps`.
+    /// It returns named values to record for the step.
+    pub fn register_recorder<F>(&mut self, n_steps: usize, func: F) -> ()
+        where F: Fn(usize, &Population<S, B>) -> Result<Vec<(String, Value)>> + Send + 'static
+    {
+        self.recorders.push((n_steps, Recorder::Custom(Box::new(func))));
+    }
+
+    /// Record the number of agents in an index every `n_steps`, as `name`.
+    pub fn record_count(&mut self, n_steps: usize, name: &str, index: &str) {
+        self.recorders.push((n_steps, Recorder::Count(name.to_owned(), index.to_owned())));
+    }
+
+    /// Record the mean of `func` over the agents in an index every `n_steps`, as `name`.
+    /// If the index is empty, `Value::Missing` is recorded instead.
+    pub fn record_mean<F>(&mut self, n_steps: usize, name: &str, index: &str, func: F)
+        where F: Fn(&S::State) -> f64 + Send + 'static
+    {
+        self.recorders.push((n_steps, Recorder::Mean(name.to_owned(), index.to_owned(), Box::new(func))));
+    }
+
+    /// Record a histogram of `func` over the agents in an index every `n_steps`,
+    /// with bins between consecutive `edges`, each as `name[lo,hi)`.
+    pub fn record_histogram<F>(&mut self, n_steps: usize, name: &str, index: &str, edges: Vec<f64>, func: F)
+        where F: Fn(&S::State) -> f64 + Send + 'static
+    {
+        self.recorders.push((n_steps,
+                             Recorder::Histogram(name.to_owned(), index.to_owned(), edges, Box::new(func))));
+    }
+
+    /// Write recorded values to a file as they're recorded.
+    ///
+    /// CSV columns are fixed by the first step recorded, so values first recorded later
+    /// are left out of the file (with a warning), though they're still in `records`
+    /// and `save_records`; JSON-lines has no such restriction.
+    pub fn set_record_output<P: AsRef<Path>>(&mut self, path: P, format: RecordFormat) {
+        self.record_output = Some((path.as_ref().to_path_buf(), format));
+    }
+
+    /// The values recorded over the last run.
+    pub fn records(&self) -> Result<Vec<Row>> {
+        Ok(self.recording.lock()?.rows().to_vec())
+    }
+
+    /// Save the values recorded over the last run to a file.
+    pub fn save_records<P: AsRef<Path>>(&self, path: P, format: RecordFormat) -> Result<()> {
+        let recording = self.recording.lock()?;
+        write_rows(File::create(path)?, recording.rows(), format)
+    }
+
+    /// Export the population every `n_steps` to a file in `dir` named by the step,
+    /// e.g. `dir/100.jsonl`. CSV snapshots don't include the world,
+    /// so it's saved alongside them as JSON, e.g. `dir/100.world.json`.
+    pub fn set_snapshots<P: AsRef<Path>>(&mut self, n_steps: usize, dir: P, format: RecordFormat) {
+        self.snapshots = Some((n_steps, dir.as_ref().to_path_buf(), format));
+    }
+
+    /// Export the population, if a snapshot is due at this step.
+    fn snapshot(&self, step: usize, population: &Population<S, B>) -> Result<()> {
+        if let Some((interval, ref dir, format)) = self.snapshots {
+            if step % interval == 0 {
+                fs::create_dir_all(dir)?;
+                let ext = match format {
+                    RecordFormat::Csv => "csv",
+                    RecordFormat::JsonLines => "jsonl",
+                };
+                let file = File::create(dir.join(format!("{}.{}", step, ext)))?;
+                population.export(BufWriter::new(file), format)?;
+                if format == RecordFormat::Csv {
+                    let file = File::create(dir.join(format!("{}.world.json", step)))?;
+                    AgentWriter::new(file, RecordFormat::JsonLines).write_world(&population.world()?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run any recorders due at this step.
+    fn record(&self, step: usize, population: &Population<S, B>) -> Result<()> {
+        let mut values = Vec::new();
+        for &(interval, ref recorder) in &self.recorders {
+            if step % interval == 0 {
+                values.extend(recorder.record(step, population)?);
+            }
+        }
+        if values.is_empty() {
+            return Ok(());
+        }
+        self.recording.lock()?.push(Row {
+            step: step,
+            values: values,
+        })
+    }
+
+    /// Block until all workers have finished the current phase,
+    /// or fail if any of them report a failure.
+    /// Returns what the workers reported when they finished.
+    ///
+    /// Meanwhile, keep an eye out for dead or stuck workers.
+    fn wait_until_finished(&self, phase: &str, coord: &mut Coordinator) -> Result<Vec<PhaseReport>> {
+        let keys = vec![finished_key(&coord.run, coord.seq), failures_key(&coord.run)];
+        let mut reports: Vec<PhaseReport> = Vec::new();
+        let mut started = Instant::now();
+        loop {
+            // only count slots finished by their current owner,
+            // in case a worker we gave up on finishes late
+            let unfinished: FnvHashMap<usize, String> = coord.owners
+                .iter()
+                .filter(|&(slot, owner)| {
+                    !reports.iter().any(|r| r.uid == *owner && r.slots.contains(slot))
+                })
+                .map(|(slot, owner)| (*slot, owner.clone()))
+                .collect();
+            if unfinished.is_empty() {
+                reports.retain(|r| coord.members.contains(&r.uid));
+                return Ok(reports);
+            }
+
+            match self.conn.blpop(&keys, Duration::from_millis(BARRIER_WAIT_MS))? {
+                Some((ref key, data)) if *key == keys[1] => {
+                    let (id, msg): (usize, String) = decode(data)?;
+                    return Err(DjinnError::WorkerFailed(id, msg));
+                }
+                Some((_, data)) => {
+                    reports.push(decode(data)?);
+                    continue;
+                }
+                None => (),
+            }
+
+            let members = coord.members.clone();
+            let dead = coord.monitor.check(&self.conn, &members)?;
+            if !dead.is_empty() {
+                self.handle_failed(dead, phase, coord, false)?;
+                started = Instant::now();
+            } else if let Some(timeout) = self.phase_timeout {
+                if started.elapsed() > timeout {
+                    let mut stuck: Vec<String> = unfinished.values().cloned().collect();
+                    stuck.sort();
+                    stuck.dedup();
+                    self.handle_failed(stuck, phase, coord, true)?;
+                    started = Instant::now();
+                }
+            }
+        }
+    }
+
+    /// Deal with dead or stuck workers (by uid) according to the failure policy.
+    fn handle_failed(&self,
+                     failed: Vec<String>,
+                     phase: &str,
+                     coord: &mut Coordinator,
+                     stuck: bool)
+                     -> Result<()> {
+        let survivors: Vec<String> = coord.members
+            .iter()
+            .filter(|uid| !failed.contains(uid))
+            .cloned()
+            .collect();
+        if self.failure_policy == FailurePolicy::Abort || survivors.is_empty() {
+            let uid = failed[0].clone();
+            return Err(if stuck {
+                DjinnError::WorkerStuck(uid)
+            } else {
+                DjinnError::WorkerDied(uid)
+            });
+        }
+
+        for uid in failed {
+            self.log(&format!("Worker {} is {}, reassigning its agents",
+                              uid,
+                              if stuck { "stuck" } else { "dead" }));
+            self.conn.publish("command", &format!("evict {}", uid))?;
+            self.conn.srem("workers", vec![member(&uid)])?;
+            coord.members.retain(|m| *m != uid);
+            coord.monitor.forget(&uid);
+
+            let slots: Vec<usize> = coord.owners
+                .iter()
+                .filter(|&(_, owner)| *owner == uid)
+                .map(|(slot, _)| *slot)
+                .collect();
+            for slot in slots {
+                // hand the slot to the survivor with the fewest slots
+                let survivor = survivors.iter()
+                    .min_by_key(|s| coord.owners.values().filter(|o| o == s).count())
+                    .unwrap()
+                    .clone();
+                coord.owners.insert(slot, survivor.clone());
+                self.conn.publish("command",
+                             &format!("adopt {} {} {} {} {} {}",
+                                      survivor,
+                                      slot,
+                                      phase,
+                                      coord.seq,
+                                      coord.step,
+                                      coord.stage))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Spawn an agent.
+    pub fn spawn(&mut self, state: S::State) -> Result<u64> {
+        let step = self.restored.as_ref().map_or(0, |checkpoint| checkpoint.step);
+        self.n_spawned += 1;
+        let id = mix(sender_key(self.seed, step, MANAGER_SENDER), self.n_spawned);
+        let update = PopulationUpdate::Spawn(id, state);
+        let data = S::Codec::encode(&update)?;
+        self.initial_pop.push(data);
+        Ok(id)
+    }
+
+    /// Spawn an agent with its id moved to a part of the id space,
+    /// see `Updates::spawn_placed`.
+    pub fn spawn_placed(&mut self, state: S::State, placement: f64) -> Result<u64> {
+        let step = self.restored.as_ref().map_or(0, |checkpoint| checkpoint.step);
+        self.n_spawned += 1;
+        let id = place(mix(sender_key(self.seed, step, MANAGER_SENDER), self.n_spawned), placement);
+        let update = PopulationUpdate::Spawn(id, state);
+        let data = S::Codec::encode(&update)?;
+        self.initial_pop.push(data);
+        Ok(id)
+    }
+
+    /// Spawn multiple agents.
+    pub fn spawns(&mut self, mut states: Vec<S::State>) -> Result<Vec<u64>> {
+        states.drain(..).map(|s| self.spawn(s)).collect()
+    }
+
+    /// Get the number of workers.
+    pub fn n_workers(&self) -> Result<usize> {
+        self.conn.scard("workers")
+    }
+}
+
+/// A process that computes and applies updates for a local population of agents.
+///
+/// A worker starts out responsible for one slot (partition) of the population,
+/// but may adopt the slots of workers that have died.
+///
+/// Workers stay up across runs: between runs they sit idle until
+/// a manager starts another run or shuts them down.
+pub struct Worker<S: Simulation, B: Backend> {
+    id: usize,
+    uid: Uuid,
+    run: Option<String>,
+    manager: B,
+    population: Population<S, B>,
+    local: FnvHashMap<usize, FnvHashMap<u64, Agent<S::State>>>,
+    updates: FnvHashMap<u64, Vec<(Stamp, S::Update)>>,
+    simulation: S,
+    queued_updates: Updates<S>,
+    evicted: bool,
+
+    /// Sleeping agents, by id, with the step they fell asleep
+    /// and the step they'll wake up at, if any.
+    dormant: FnvHashMap<u64, (usize, Option<usize>)>,
+
+    /// Backend writes queued by local agents, applied in the update phase.
+    ops: Vec<BackendOp>,
+
+    /// Time spent on each local slot this step, in microseconds.
+    busy: FnvHashMap<usize, u64>,
+
+    verbose: bool,
+}
+
+/// Get a readable message out of a panic payload.
+fn panic_message(err: Box<Any + Send>) -> String {
+    match err.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(err) => {
+            match err.downcast::<&'static str>() {
+                Ok(msg) => (*msg).to_owned(),
+                Err(_) => "worker panicked".to_owned(),
+            }
+        }
+    }
+}
+
+impl<S: Simulation, B: Backend + 'static> Worker<S, B> {
+    pub fn new(conn: B, simulation: S) -> Worker<S, B> {
+        let hasher = WHasher::new(0, simulation.partitioner());
         Worker {
             id: 0,
             uid: Uuid::new_v4(),
-            manager: client.get_connection().unwrap(),
+            run: None,
+            manager: conn.clone(),
             population: Population::new(simulation.clone(), conn),
             simulation: simulation,
             local: FnvHashMap::default(),
             updates: FnvHashMap::default(),
-            pubsub: client.get_pubsub().unwrap(),
             queued_updates: Updates::new(hasher),
+            evicted: false,
+            dormant: FnvHashMap::default(),
+            ops: Vec::new(),
+            busy: FnvHashMap::default(),
+            verbose: false,
+        }
+    }
+
+    /// Print what the worker is doing, e.g. when it fails, to stdout.
+    /// Off by default.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    fn log(&self, msg: &str) {
+        if self.verbose {
+            println!("{}", msg);
         }
     }
 
-    /// Start running the worker. This blocks until the worker receives a `terminate` command.
-    pub fn start(&mut self) {
+    /// Start running the worker. This blocks until the worker receives a `shutdown` command.
+    ///
+    /// Failures while processing a command, including panics in simulation code,
+    /// are reported to the manager rather than returned. An error is returned only if
+    /// the worker can no longer communicate with the manager.
+    pub fn start(&mut self) -> Result<()> {
+        // subscribe to the command channel,
+        // before registering so we don't miss the manager's `start`
+        let mut commands = self.manager.subscribe("command")?;
+
+        // start sending heartbeats before registering,
+        // so the manager never sees us without one
+        let heartbeat = Heartbeat::start(self.manager.clone(), self.uid.to_string());
+
         // register with the manager
-        let _: () = self.manager.sadd("workers", self.uid.to_string()).unwrap();
-
-        // subscribe to the command channel
-        self.pubsub.subscribe("command").unwrap();
-
-        // each iteration of this loop is one simulation run
-        'outer: loop {
-            // reset
-            self.local.clear();
-            self.updates.clear();
-
-            // wait til we get the go-ahead from the manager
-            let mut started = false;
-            while !started {
-                let msg = self.pubsub.get_message().unwrap();
-                let payload: String = msg.get_payload().unwrap();
-                started = payload == "start";
-            }
-
-            // get an id
-            self.id = self.manager.lpop("worker_ids").unwrap();
-            let n_workers = self.manager.scard::<&str, usize>("workers").unwrap();
-            self.queued_updates.hasher = WHasher::new(n_workers);
-            self.population.hasher = Some(self.queued_updates.hasher.clone());
-
-            'inner: loop {
-                let msg = self.pubsub.get_message().unwrap();
-                let payload: String = msg.get_payload().unwrap();
-                self.process_cmd(payload.as_ref());
-                if payload == "terminate" {
-                    break 'outer; // TODO eventually we will want to just break this inner loop, i.e. end one run of the simulation but keep the worker up for more
-                }
+        let res = self.manager
+            .sadd("workers", vec![member(self.uid)])
+            .and_then(|_| self.serve(&mut commands));
+        heartbeat.stop();
+        let unregistered = self.manager.srem("workers", vec![member(self.uid)]);
+        res.and(unregistered)
+    }
+
+    fn serve(&mut self, commands: &mut B::Subscription) -> Result<()> {
+        loop {
+            let payload = commands.next_message()?;
+            let res = match panic::catch_unwind(AssertUnwindSafe(|| self.process_cmd(payload.as_ref()))) {
+                Ok(res) => res,
+                Err(err) => Err(DjinnError::Protocol(panic_message(err))),
+            };
+            match res {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(err) => self.report_failure(err)?,
             }
         }
     }
 
-    /// Fetch queued new agents assigned to this worker
-    /// and kill those queued to die.
-    fn sync_population(&mut self) {
-        let key = format!("spawn:{}", self.id);
-        let datas: Vec<Vec<u8>> = self.population
-            .conn
-            .lrange(&key, 0, -1)
-            .unwrap();
-        if !datas.is_empty() {
-            let _: () = self.population.conn.del(&key).unwrap();
-            for data in datas {
-                let a: Agent<S::State> = decode(data).unwrap();
-                self.local.insert(a.id, a);
+    /// Let the manager know that this worker failed.
+    fn report_failure(&self, err: DjinnError) -> Result<()> {
+        let msg = match err {
+            DjinnError::Protocol(msg) => msg,
+            err => format!("{}", err),
+        };
+        self.log(&format!("Worker {} failed: {}", self.id, msg));
+
+        // if we're between runs, there's no one to tell
+        match self.run {
+            Some(ref run) => {
+                let data = encode((self.id, msg))?;
+                self.manager.lpush(&failures_key(run), vec![data])
             }
+            None => Ok(()),
         }
+    }
 
-        let key = format!("kill:{}", self.id);
-        let ids: Vec<u64> = self.population
-            .conn
-            .lrange(&key, 0, -1)
-            .unwrap();
-        if !ids.is_empty() {
-            let _: () = self.population.conn.del(&key).unwrap();
-            for id in ids {
-                self.local.remove(&id);
+    /// Join a run, if the manager assigned us an id for it.
+    /// Returns whether we joined.
+    fn start_run(&mut self, run: &str, n_slots: usize) -> Result<bool> {
+        self.id = match self.manager.get(&worker_id_key(run, &self.uid.to_string()))? {
+            Some(id) => from_member(id)?,
+            None => {
+                // we registered too late for this run
+                self.log(&format!("Worker {} sitting out run {}", self.uid, run));
+                return Ok(false);
+            }
+        };
+        self.run = Some(run.to_owned());
+        self.queued_updates.hasher = WHasher::new(n_slots, self.simulation.partitioner());
+        self.queued_updates.schedule = self.simulation.schedule();
+        self.queued_updates.seed = match self.manager.get(SEED_KEY)? {
+            Some(seed) => from_member(seed)?,
+            None => return Err(DjinnError::Protocol("the seed has not been set".to_owned())),
+        };
+        self.population.hasher = Some(self.queued_updates.hasher.clone());
+        Ok(true)
+    }
+
+    /// Clear out the state of a run.
+    fn end_run(&mut self) {
+        self.run = None;
+        self.local.clear();
+        self.updates.clear();
+        self.queued_updates.clear();
+        self.dormant.clear();
+        self.ops.clear();
+        self.busy.clear();
+        self.population.end_reads();
+        self.evicted = false;
+    }
+
+    /// Fetch queued new agents assigned to these slots
+    /// and kill those queued to die, in one request.
+    fn sync_population(&mut self, slots: &[usize]) -> Result<()> {
+        let keys: Vec<String> = slots.iter()
+            .flat_map(|slot| vec![format!("spawn:{}", slot), format!("kill:{}", slot)])
+            .collect();
+        let mut lists = self.population.conn.take_lists(&keys)?.into_iter();
+        for slot in slots {
+            let local = self.local.entry(*slot).or_insert_with(FnvHashMap::default);
+            for data in lists.next().unwrap_or_default() {
+                let a: Agent<S::State> = S::Codec::decode(&data)?;
+                local.insert(a.id, a);
+            }
+            for id in lists.next().unwrap_or_default() {
+                let id = from_member(id)?;
+                local.remove(&id);
+                self.dormant.remove(&id);
             }
         }
+        Ok(())
     }
 
-    fn process_cmd(&mut self, cmd: &str) {
-        match cmd {
-            "terminate" => {
-                let _: () = self.manager.srem("workers", self.uid.to_string()).unwrap();
+    /// Take over a slot from another worker,
+    /// loading its agents from the backend.
+    fn adopt(&mut self, slot: usize) -> Result<()> {
+        let ids: Vec<u64> = {
+            let hasher = self.population.hasher()?;
+            from_members(self.population.conn.smembers(POPULATION_KEY)?)?
+                .into_iter()
+                .filter(|id| hasher.hash(*id) == slot)
+                .collect()
+        };
+        let agents = self.population.get_agents(ids)?;
+        {
+            let local = self.local.entry(slot).or_insert_with(FnvHashMap::default);
+            for agent in agents {
+                local.insert(agent.id, agent);
             }
-            "decide" => {
-                self.decide();
-                let _: () = self.manager.sadd("finished", self.id).unwrap();
+        }
+        self.load_dormant(&[slot])
+    }
+
+    /// Pick up which agents of these slots were asleep, as of the last update phase.
+    fn load_dormant(&mut self, slots: &[usize]) -> Result<()> {
+        let keys: Vec<String> = slots.iter().map(|slot| dormant_key(*slot)).collect();
+        for data in self.population.conn.get_multiple(&keys)? {
+            if let Some(data) = data {
+                let sleeping: Vec<(u64, usize, Option<usize>)> = decode(data)?;
+                for (id, since, wake) in sleeping {
+                    self.dormant.insert(id, (since, wake));
+                }
             }
-            "update" => {
-                self.update();
-                let _: () = self.manager.sadd("finished", self.id).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Save which agents of these slots are asleep, so that they stay asleep
+    /// if the slots move to another worker or the run is resumed from a checkpoint.
+    fn dormant_ops(&self, slots: &[usize]) -> Result<Vec<BackendOp>> {
+        let mut ops = Vec::with_capacity(slots.len());
+        for slot in slots {
+            let sleeping: Vec<(u64, usize, Option<usize>)> = match self.local.get(slot) {
+                Some(local) => {
+                    local.keys()
+                        .filter_map(|id| self.dormant.get(id).map(|&(since, wake)| (*id, since, wake)))
+                        .collect()
+                }
+                None => continue,
+            };
+            ops.push(if sleeping.is_empty() {
+                BackendOp::Del(dormant_key(*slot))
+            } else {
+                BackendOp::Set(dormant_key(*slot), encode(sleeping)?)
+            });
+        }
+        Ok(ops)
+    }
+
+    /// Hand a slot over to another worker. Its agents' states
+    /// are already in the backend as of the last update phase.
+    fn release(&mut self, slot: usize) {
+        if let Some(local) = self.local.remove(&slot) {
+            for id in local.keys() {
+                self.updates.remove(id);
+                self.dormant.remove(id);
             }
+        }
+        self.busy.remove(&slot);
+    }
+
+    /// Process a command from the manager,
+    /// returning whether or not to keep listening for more.
+    fn process_cmd(&mut self, cmd: &str) -> Result<bool> {
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+        let in_run = self.run.is_some() && !self.evicted;
+        match args.get(0).cloned() {
+            Some("shutdown") => return Ok(false),
+            Some("register") => {
+                if self.run.is_none() {
+                    self.manager.sadd("workers", vec![member(self.uid)])?;
+                }
+            }
+            Some("start") => {
+                if self.run.is_none() {
+                    let run = args.get(1).cloned().unwrap_or("");
+                    let n_slots = parse_arg(&args, 2)?;
+                    let n_workers = parse_arg(&args, 3)?;
+                    if self.start_run(run, n_slots)? {
+                        // take our block of slots
+                        let per_worker = n_slots / cmp::max(n_workers, 1);
+                        let slots: Vec<usize> = (self.id * per_worker..(self.id + 1) * per_worker).collect();
+                        for slot in &slots {
+                            self.local.insert(*slot, FnvHashMap::default());
+                        }
+                        self.load_dormant(&slots)?;
+                    }
+                }
+            }
+            Some("join") => {
+                // joining a run that's already going,
+                // the manager will hand us slots after
+                if self.run.is_none() && args.get(2).cloned() == Some(&self.uid.to_string()) {
+                    let run = args.get(1).cloned().unwrap_or("");
+                    let n_slots = parse_arg(&args, 3)?;
+                    self.start_run(run, n_slots)?;
+                }
+            }
+            Some("retire") => {
+                if self.run.as_ref().map(|run| run.as_str()) == args.get(1).cloned() &&
+                   args.get(2).cloned() == Some(&self.uid.to_string()) {
+                    self.log(&format!("Worker {} retiring", self.uid));
+                    self.end_run();
+                    return Ok(false);
+                }
+            }
+            Some("end_run") => {
+                if self.run.as_ref().map(|run| run.as_str()) == args.get(1).cloned() {
+                    self.end_run();
+                }
+            }
+            Some(phase) if PHASES.contains(&phase) => {
+                if in_run {
+                    let seq = parse_arg(&args, 1)?;
+                    let step = parse_arg(&args, 2)?;
+                    let stage = parse_arg(&args, 3)?;
+                    let slots: Vec<usize> = self.local.keys().cloned().collect();
+                    self.run_phase(phase, &slots, seq, step, stage)?;
+                }
+            }
+            Some("adopt") => {
+                if in_run && args.get(1).cloned() == Some(&self.uid.to_string()) {
+                    let slot = parse_arg(&args, 2)?;
+                    let phase = args.get(3).cloned().unwrap_or("");
+                    let seq = parse_arg(&args, 4)?;
+                    let step = parse_arg(&args, 5)?;
+                    let stage = parse_arg(&args, 6)?;
+                    self.adopt(slot)?;
+                    self.run_phase(phase, &[slot], seq, step, stage)?;
+                }
+            }
+            Some("migrate") => {
+                if in_run {
+                    let seq = parse_arg(&args, 1)?;
+                    let step = parse_arg(&args, 2)?;
+                    let stage = parse_arg(&args, 3)?;
+                    let uid = self.uid.to_string();
+                    for mv in args.get(4).cloned().unwrap_or("").split(',') {
+                        let parts: Vec<&str> = mv.splitn(2, ':').collect();
+                        let slot = parse_arg(&parts, 0)?;
+                        let owner = parts.get(1).cloned().unwrap_or("");
+                        if owner == uid && !self.local.contains_key(&slot) {
+                            self.adopt(slot)?;
+                        } else if owner != uid && self.local.contains_key(&slot) {
+                            self.release(slot);
+                        }
+                    }
+                    let slots: Vec<usize> = self.local.keys().cloned().collect();
+                    self.run_phase("migrate", &slots, seq, step, stage)?;
+                }
+            }
+            Some("evict") => {
+                if in_run && args.get(1).cloned() == Some(&self.uid.to_string()) {
+                    // the manager gave up on us,
+                    // so sit out the rest of the run
+                    self.evicted = true;
+                    self.local.clear();
+                    self.updates.clear();
+                    self.dormant.clear();
+                    self.ops.clear();
+                    self.busy.clear();
+                    self.population.end_reads();
+                }
+            }
+            _ => self.log(&format!("Unrecognized command: {}", cmd)),
+        }
+        Ok(true)
+    }
+
+    /// Run a phase for the specified slots,
+    /// then let the manager know they're finished.
+    fn run_phase(&mut self,
+                 phase: &str,
+                 slots: &[usize],
+                 seq: usize,
+                 step: usize,
+                 stage: usize)
+                 -> Result<()> {
+        let started = Instant::now();
+        match phase {
             "sync" => {
-                self.sync_population();
-                let _: () = self.manager.sadd("finished", self.id).unwrap();
+                self.sync_population(slots)?;
+                for slot in slots {
+                    self.busy.insert(*slot, 0);
+                }
+            }
+            "decide" => self.decide(slots, step, stage)?,
+            "update" => {
+                let ops = self.ops.drain(..).collect();
+                self.population.conn.apply(ops)?;
+
+                // get updates queued by other workers, and those delayed until this step,
+                // for all our slots in one request
+                let keys: Vec<String> = slots.iter()
+                    .flat_map(|slot| vec![format!("updates:{}", slot), pending_key(step, *slot)])
+                    .collect();
+                let mut lists = self.population.conn.take_lists(&keys)?.into_iter();
+                for slot in slots {
+                    let started = Instant::now();
+                    let mut remote_updates = lists.next().unwrap_or_default();
+                    remote_updates.extend(lists.next().unwrap_or_default());
+                    self.update(*slot, step, remote_updates)?;
+                    *self.busy.entry(*slot).or_insert(0) += micros(started.elapsed());
+                }
+                self.report_load(slots)?;
             }
-            s => println!("Unrecognized command: {}", s),
+            // slots were already handed over when the command came in
+            "migrate" => (),
+            _ => return Err(DjinnError::Protocol(format!("unknown phase: {}", phase))),
+        }
+        let run = match self.run {
+            Some(ref run) => run,
+            None => return Ok(()),
+        };
+        let report = PhaseReport {
+            uid: self.uid.to_string(),
+            slots: slots.to_vec(),
+            n_agents: slots.iter().filter_map(|slot| self.local.get(slot)).map(|local| local.len()).sum(),
+            duration: started.elapsed(),
+        };
+        self.manager.lpush(&finished_key(run, seq), vec![encode(report)?])
+    }
+
+    /// Let the manager know how many agents each slot has
+    /// and how much time was spent on them this step, for rebalancing,
+    /// and save which of them are asleep.
+    fn report_load(&self, slots: &[usize]) -> Result<()> {
+        let run = match self.run {
+            Some(ref run) => run,
+            None => return Ok(()),
+        };
+        let mut ops = self.dormant_ops(slots)?;
+        for slot in slots {
+            let n_agents = self.local.get(slot).map_or(0, |local| local.len());
+            let busy = self.busy.get(slot).cloned().unwrap_or(0);
+            ops.push(BackendOp::Set(load_key(run, *slot), member(format!("{} {}", n_agents, busy))));
         }
+        self.manager.apply(ops)
     }
 
-    fn decide(&mut self) {
-        let world = self.population.world();
-        for agent in self.local.values() {
-            self.simulation.decide(agent, &world, &self.population, &mut self.queued_updates);
+    fn decide(&mut self, slots: &[usize], step: usize, stage: usize) -> Result<()> {
+        let world = self.population.world()?;
+        let schedule = self.queued_updates.schedule;
+        let staged = schedule.n_stages() > 1;
+        let event_driven = schedule == Schedule::DiscreteEvent;
+
+        // let agents read each other (and any agent read before) without the backend,
+        // handing our agents to the population for the phase
+        let local = Arc::new(mem::replace(&mut self.local, FnvHashMap::default()));
+        self.population.begin_reads(local.clone());
+        for slot in slots {
+            let started = Instant::now();
+            if let Some(local) = local.get(slot) {
+                // decide in the order the schedule ranks agents at this step,
+                // e.g. shuffled for `Schedule::Shuffled`
+                let seed = self.queued_updates.seed;
+                let mut order: Vec<(u64, &Agent<S::State>)> = local.values()
+                    .map(|agent| (schedule.rank(sender_key(seed, step, agent.id), agent.id), agent))
+                    .collect();
+                order.sort_by_key(|&(rank, _)| rank);
+                for (_, agent) in order {
+                    if staged && self.simulation.stage(agent) != stage {
+                        continue;
+                    }
+                    let interval = self.simulation.interval(agent) as u64;
+                    if !event_driven && interval > 1 && (step as u64).wrapping_add(agent.id) % interval != 0 {
+                        continue;
+                    }
+                    match self.dormant.get(&agent.id) {
+                        Some(&(_, None)) => continue,
+                        Some(&(_, Some(wake))) if step < wake => continue,
+                        _ => (),
+                    }
+                    self.queued_updates.begin(step, agent.id);
+                    if !schedule.is_active(self.queued_updates.key) {
+                        continue;
+                    }
+                    if event_driven {
+                        // agents only act when something is due for them
+                        self.queued_updates.sleep();
+                    }
+                    self.population.begin_draws(self.queued_updates.key);
+                    self.simulation
+                        .decide(agent, &world, &self.population, &mut self.queued_updates);
+                }
+            }
+            *self.busy.entry(*slot).or_insert(0) += micros(started.elapsed());
+        }
+        self.population.end_reads();
+        self.local = Arc::try_unwrap(local).unwrap_or_else(|local| (*local).clone());
+
+        // wake agents whose time has come,
+        // and put to sleep those that asked to
+        self.dormant.retain(|_, &mut (_, wake)| wake.map_or(true, |wake| step < wake));
+        let mut due = Vec::new();
+        for (id, wake) in self.queued_updates.sleeps.drain(..) {
+            if let Some(wake) = wake {
+                due.push(cmp::max(wake, step + 1));
+            }
+            self.dormant.insert(id, (step, wake));
+        }
+        if event_driven && !due.is_empty() {
+            due.sort();
+            due.dedup();
+            self.manager.sadd(PENDING_KEY, due.into_iter().map(member).collect())?;
         }
 
+        // hold on to backend writes until the update phase
+        self.ops.extend(self.queued_updates.ops.drain(..));
+
         // push out updates
         // first grab local updates
-        match self.queued_updates.updates.remove(&self.id) {
-            Some(updates) => {
-                for (id, update) in updates {
-                    self.updates.entry(id).or_insert_with(Vec::new).push(update);
+        for slot in self.local.keys() {
+            match self.queued_updates.updates.remove(slot) {
+                Some(updates) => {
+                    for (id, stamp, update) in updates {
+                        self.updates.entry(id).or_insert_with(Vec::new).push((stamp, update));
+                    }
                 }
-            }
-            None => (),
-        };
-        self.queued_updates.push(&self.population);
+                None => (),
+            };
+        }
+        self.queued_updates.push(&self.population)
     }
 
-    fn update(&mut self) {
-        let mut to_change: Vec<(u64, &S::State)> = Vec::with_capacity(self.local.len());
-
-        // get updates queued by other workers
-        let key = format!("updates:{}", self.id);
-        let remote_updates: Vec<Vec<u8>> = self.population
-            .conn
-            .lrange(&key, 0, -1)
-            .unwrap();
-        let _: () = self.population.conn.del(&key).unwrap();
+    /// Apply updates to a slot's agents, both local ones
+    /// and those from the backend (`remote_updates`).
+    fn update(&mut self, slot: usize, step: usize, remote_updates: Vec<Vec<u8>>) -> Result<()> {
+        let event_driven = self.queued_updates.schedule == Schedule::DiscreteEvent;
+        let local = match self.local.get_mut(&slot) {
+            Some(local) => local,
+            None => return Ok(()),
+        };
+        let mut to_change: Vec<(u64, &S::State)> = Vec::with_capacity(local.len());
 
         for data in remote_updates {
-            let (id, update) = decode(data).unwrap();
-            self.updates.entry(id).or_insert_with(Vec::new).push(update);
+            let (id, stamp, update) = S::Codec::decode(&data)?;
+            self.updates.entry(id).or_insert_with(Vec::new).push((stamp, update));
         }
 
-        for agent in self.local.values_mut() {
+        let mut woken = false;
+        for agent in local.values_mut() {
             let updates = match self.updates.get_mut(&agent.id) {
-                Some(updates) => updates.drain(..).collect(),
+                Some(updates) => {
+                    // sleeping agents are only woken by updates
+                    // that arrive after the step they fell asleep,
+                    // except in discrete-event mode, where any update wakes them
+                    if let Some(&(since, _)) = self.dormant.get(&agent.id) {
+                        if updates.is_empty() {
+                            continue;
+                        } else if since < step || event_driven {
+                            self.dormant.remove(&agent.id);
+                        }
+                    }
+                    woken = woken || !updates.is_empty();
+                    updates.sort_by_key(|&(stamp, _)| stamp);
+                    updates.drain(..).map(|(_, update)| update).collect()
+                }
                 None => continue,
             };
             let changed = self.simulation.update(&mut agent.state, updates);
@@ -688,56 +2561,105 @@ impl<S: Simulation, C: Redis> Worker<S, C> {
             };
         }
         if !to_change.is_empty() {
-            self.population.set_agents(&to_change);
+            self.population.set_agents(&to_change)?;
+        }
+
+        // agents that received updates act next step
+        if event_driven && woken {
+            self.manager.sadd(PENDING_KEY, vec![member(step + 1)])?;
         }
+        Ok(())
+    }
+}
+
+/// Parse a numeric command argument.
+fn parse_arg(args: &[&str], i: usize) -> Result<usize> {
+    match args.get(i).and_then(|arg| arg.parse().ok()) {
+        Some(val) => Ok(val),
+        None => Err(DjinnError::Protocol(format!("malformed command: {}", args.join(" ")))),
     }
 }
 
 /// Convenience function for running a simulation/manager with `n` local workers.
-/// This blocks until the simulation is finished running.
-pub fn run<S: Simulation + 'static, R: Redis + 'static>(sim: S,
-                                                        world: S::World,
-                                                        manager: Manager<S, R>,
-                                                        n_workers: usize,
-                                                        n_steps: usize)
-                                                        -> Manager<S, R> {
-
-    let addr = manager.addr.clone();
-    let pop_client = manager.population.conn.clone();
+/// This blocks until the simulation is finished running,
+/// returning the manager along with a summary of the run.
+pub fn run<S: Simulation + 'static, B: Backend + 'static>(sim: S,
+                                                          world: S::World,
+                                                          manager: Manager<S, B>,
+                                                          n_workers: usize,
+                                                          n_steps: usize)
+                                                          -> Result<(Manager<S, B>, RunSummary)> {
+
+    let conn = manager.conn.clone();
     let sim_m = sim.clone();
 
-    // run the manager on a separate thread
+    // run the manager on a separate thread,
+    // shutting down the workers when it's done
     let manager_t = thread::spawn(move || {
-        manager.run(sim_m, world, n_steps);
-        manager
+        let res = manager.run(sim_m, world, n_steps);
+        let shutdown = manager.shutdown();
+        let summary = res?;
+        shutdown.map(|_| (manager, summary))
     });
 
-    run_workers(addr.as_ref(), pop_client, sim.clone(), n_workers);
-    manager_t.join().unwrap()
+    let workers_res = run_workers(conn, sim.clone(), n_workers);
+    let manager_res = match manager_t.join() {
+        Ok(res) => res,
+        Err(err) => Err(DjinnError::Protocol(panic_message(err))),
+    };
+
+    // the manager's error is more informative
+    // than the workers' (which just lost their manager)
+    match manager_res {
+        Ok(res) => workers_res.map(|_| res),
+        Err(err) => Err(err),
+    }
+}
+
+/// Shut down all workers listening on a backend.
+pub fn shutdown_workers<B: Backend>(conn: &B) -> Result<()> {
+    conn.publish("command", "shutdown")
+}
+
+/// Ask the manager of the current run to let go of `n_workers` workers
+/// at the end of the step, e.g. to scale down a cluster.
+/// Their slots are handed to the remaining workers, and retired workers stop,
+/// as if they'd been shut down. A run always keeps at least one worker.
+///
+/// To scale up, just start more workers (e.g. with `run_workers`);
+/// they join the run at the end of the step.
+pub fn retire_workers<B: Backend>(conn: &B, n_workers: usize) -> Result<()> {
+    conn.lpush(RETIRE_KEY, vec![member(n_workers)])
 }
 
 /// Convenience function to run a node of n workers.
-/// This blocks until the workers are done.
-pub fn run_workers<S: Simulation + 'static, R: Redis + 'static>(addr: &str,
-                                                                pop_client: R,
-                                                                sim: S,
-                                                                n_workers: usize) {
-    let addr = addr.to_owned();
-    let worker_ts: Vec<thread::JoinHandle<()>> = (0..n_workers)
+/// This blocks until the workers are shut down.
+pub fn run_workers<S: Simulation + 'static, B: Backend + 'static>(conn: B,
+                                                                  sim: S,
+                                                                  n_workers: usize)
+                                                                  -> Result<()> {
+    let worker_ts: Vec<thread::JoinHandle<Result<()>>> = (0..n_workers)
         .map(|_| {
             // create a worker on a separate thread
-            let addr = addr.clone();
             let sim = sim.clone();
-            let pop_client = pop_client.clone();
+            let conn = conn.clone();
             thread::spawn(move || {
-                let mut worker = Worker::new(addr.as_ref(), pop_client, sim);
-                worker.start();
+                let mut worker = Worker::new(conn, sim);
+                worker.start()
             })
         })
         .collect();
 
     // block til done running
+    let mut res = Ok(());
     for t in worker_ts {
-        t.join().unwrap();
+        let worker_res = match t.join() {
+            Ok(worker_res) => worker_res,
+            Err(err) => Err(DjinnError::Protocol(panic_message(err))),
+        };
+        if res.is_ok() {
+            res = worker_res;
+        }
     }
+    res
 }
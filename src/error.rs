@@ -0,0 +1,96 @@
+use std::fmt;
+use std::error;
+use std::io;
+use std::result;
+use std::sync::PoisonError;
+use std::sync::mpsc::RecvError;
+use redis::RedisError;
+
+/// Errors that can occur while running a simulation.
+#[derive(Debug)]
+pub enum DjinnError {
+    /// The backend could not be reached or failed to execute a command.
+    Transport(String),
+
+    /// A value could not be encoded or decoded.
+    Serialization(String),
+
+    /// A file, e.g. a checkpoint, could not be read or written.
+    Io(String),
+
+    /// There is no agent with the specified id.
+    MissingAgent(u64),
+
+    /// A message or stored value was not what the manager or a worker expected.
+    Protocol(String),
+
+    /// A worker (by id) failed while processing a command.
+    WorkerFailed(usize, String),
+
+    /// A worker (by uid) stopped sending heartbeats.
+    WorkerDied(String),
+
+    /// A worker (by uid) took too long to finish a phase.
+    WorkerStuck(String),
+
+    /// A configuration, e.g. for a sweep, was invalid.
+    Config(String),
+}
+
+pub type Result<T> = result::Result<T, DjinnError>;
+
+impl fmt::Display for DjinnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DjinnError::Transport(ref msg) => write!(f, "Transport error: {}", msg),
+            DjinnError::Serialization(ref msg) => write!(f, "Serialization error: {}", msg),
+            DjinnError::Io(ref msg) => write!(f, "IO error: {}", msg),
+            DjinnError::MissingAgent(id) => write!(f, "No agent with id {}", id),
+            DjinnError::Protocol(ref msg) => write!(f, "Protocol error: {}", msg),
+            DjinnError::WorkerFailed(id, ref msg) => write!(f, "Worker {} failed: {}", id, msg),
+            DjinnError::WorkerDied(ref uid) => write!(f, "Worker {} stopped responding", uid),
+            DjinnError::WorkerStuck(ref uid) => write!(f, "Worker {} did not finish its phase in time", uid),
+            DjinnError::Config(ref msg) => write!(f, "Config error: {}", msg),
+        }
+    }
+}
+
+impl error::Error for DjinnError {
+    fn description(&self) -> &str {
+        match *self {
+            DjinnError::Transport(_) => "transport error",
+            DjinnError::Serialization(_) => "serialization error",
+            DjinnError::Io(_) => "io error",
+            DjinnError::MissingAgent(_) => "missing agent",
+            DjinnError::Protocol(_) => "protocol error",
+            DjinnError::WorkerFailed(_, _) => "worker failed",
+            DjinnError::WorkerDied(_) => "worker died",
+            DjinnError::WorkerStuck(_) => "worker stuck",
+            DjinnError::Config(_) => "config error",
+        }
+    }
+}
+
+impl From<RedisError> for DjinnError {
+    fn from(err: RedisError) -> DjinnError {
+        DjinnError::Transport(format!("{}", err))
+    }
+}
+
+impl From<io::Error> for DjinnError {
+    fn from(err: io::Error) -> DjinnError {
+        DjinnError::Io(format!("{}", err))
+    }
+}
+
+impl<T> From<PoisonError<T>> for DjinnError {
+    fn from(_: PoisonError<T>) -> DjinnError {
+        DjinnError::Transport("backend lock poisoned".to_owned())
+    }
+}
+
+impl From<RecvError> for DjinnError {
+    fn from(_: RecvError) -> DjinnError {
+        DjinnError::Transport("channel closed".to_owned())
+    }
+}
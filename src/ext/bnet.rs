@@ -1,4 +1,4 @@
-use rand;
+use rand::Rng;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::collections::HashMap;
@@ -33,15 +33,19 @@ pub enum Var {
 
 pub struct BNet<T: Eq + PartialEq + Hash + Debug + Clone> {
     graph: HashMap<T, Vec<T>>,
+    // nodes with children, in the order they were added,
+    // so parents are always sampled in the same order
+    nodes: Vec<T>,
     dataset: Dataset<T>,
     groupers: HashMap<T, Box<Fn(&Var) -> usize>>,
-    samplers: HashMap<T, Box<Fn(usize) -> Var>>,
+    samplers: HashMap<T, Box<Fn(usize, &mut Rng) -> Var>>,
 }
 
 impl<T: Eq + PartialEq + Hash + Debug + Clone> BNet<T> {
     pub fn new(dataset: Dataset<T>) -> BNet<T> {
         BNet {
             graph: HashMap::new(),
+            nodes: Vec::new(),
             groupers: HashMap::new(),
             samplers: HashMap::new(),
             dataset: dataset,
@@ -54,21 +58,26 @@ impl<T: Eq + PartialEq + Hash + Debug + Clone> BNet<T> {
         self.groupers.insert(n, Box::new(func));
     }
 
+    /// Register a function that samples a value for a node given its group,
+    /// drawing from the specified RNG.
     pub fn register_sampler<F>(&mut self, n: T, func: F) -> ()
-        where F: Fn(usize) -> Var + 'static
+        where F: Fn(usize, &mut Rng) -> Var + 'static
     {
         self.samplers.insert(n, Box::new(func));
     }
 
     pub fn add_edge(&mut self, from: T, to: T) {
+        if !self.graph.contains_key(&from) {
+            self.nodes.push(from.clone());
+        }
         self.graph.entry(from).or_insert_with(Vec::new).push(to);
     }
 
     // Get parents for a node.
     fn parents(&self, n: &T) -> Vec<&T> {
-        self.graph
+        self.nodes
             .iter()
-            .filter_map(|(k, v)| { if v.contains(n) { Some(k) } else { None } })
+            .filter(|k| self.graph[k].contains(n))
             .collect()
     }
 
@@ -138,8 +147,10 @@ impl<T: Eq + PartialEq + Hash + Debug + Clone> BNet<T> {
             })
             .collect();
 
-        // normalize
-        let total: f64 = probs.values().sum();
+        // normalize, summing in a consistent order
+        let mut keys: Vec<usize> = probs.keys().cloned().collect();
+        keys.sort();
+        let total: f64 = keys.iter().map(|k| probs[k]).sum();
         for (_, prob) in probs.iter_mut() {
             *prob /= total;
         }
@@ -151,17 +162,28 @@ impl<T: Eq + PartialEq + Hash + Debug + Clone> BNet<T> {
     // we have to convert probs to integers...
     // we lose fidelity as a result.
     fn probs_to_weights(&self, probs: HashMap<usize, f64>) -> Vec<Weighted<usize>> {
-        probs.iter()
+        let mut weights: Vec<Weighted<usize>> = probs.iter()
             .map(|(k, p)| {
                 Weighted {
                     item: *k,
                     weight: (p * 1000.) as u32,
                 }
             })
-            .collect()
+            .collect();
+
+        // so the same draw picks the same item from run to run
+        weights.sort_by_key(|w| w.item);
+        weights
     }
 
-    pub fn sample_node(&self, n: &T, mut sampled: HashMap<T, Var>) -> HashMap<T, Var> {
+    /// Sample a node (and any of its unsampled parents) using the specified RNG,
+    /// e.g. `updates.rng()` for an agent in a simulation.
+    /// Samples are reproducible for a seeded RNG.
+    pub fn sample_node<R: Rng>(&self,
+                               n: &T,
+                               mut sampled: HashMap<T, Var>,
+                               rng: &mut R)
+                               -> HashMap<T, Var> {
         let parents = self.parents(n);
         let dist = if parents.is_empty() {
             // if no parents, use p(n)
@@ -171,7 +193,7 @@ impl<T: Eq + PartialEq + Hash + Debug + Clone> BNet<T> {
             // first, sample all parents
             for p in parents.iter() {
                 if !sampled.contains_key(p) {
-                    sampled = self.sample_node(p, sampled);
+                    sampled = self.sample_node(p, sampled, rng);
                 }
             }
 
@@ -183,13 +205,12 @@ impl<T: Eq + PartialEq + Hash + Debug + Clone> BNet<T> {
                 .collect();
             self.probs_given(n, given)
         };
-        let mut rng = rand::thread_rng();
         let mut choices = self.probs_to_weights(dist);
         let wc = WeightedChoice::new(&mut choices);
-        let choice = wc.ind_sample(&mut rng);
+        let choice = wc.ind_sample(rng);
 
         let val = match self.samplers.get(n) {
-            Some(sampler) => sampler(choice),
+            Some(sampler) => sampler(choice, rng),
             None => Var::Int(choice as i64),
         };
         sampled.insert(n.clone(), val);
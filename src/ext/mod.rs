@@ -3,3 +3,4 @@
 pub mod qlearning;
 pub mod yaml;
 pub mod bnet;
+pub mod sweep;
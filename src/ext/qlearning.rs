@@ -3,10 +3,11 @@
 //! For an example see `examples/qlearning.rs`.
 
 use rand;
-use rand::{Rng, ThreadRng};
+use rand::{Rng, XorShiftRng};
 use std::fmt::Debug;
 use std::hash::Hash;
-use std::collections::HashMap;
+use fnv::FnvHashMap;
+use hash::seeded_rng;
 
 /// The state a Q-learning agent learns from and responds to.
 pub trait State: Send + Sync + Clone + PartialEq + Hash + Eq + Debug {}
@@ -22,19 +23,23 @@ pub struct QLearnerParams<S: State, A: Action> {
     explore: f64,
     learning_rate: f64,
     prev: Option<(S, A)>,
-    pub q: HashMap<S, HashMap<A, f64>>,
-    pub rng: ThreadRng,
+    // fnv rather than std maps, so that ties between actions
+    // are broken the same way from run to run
+    pub q: FnvHashMap<S, FnvHashMap<A, f64>>,
+    pub rng: XorShiftRng,
 }
 
 impl<S: State, A: Action> QLearnerParams<S, A> {
-    pub fn new(discount: f64, explore: f64, learning_rate: f64) -> QLearnerParams<S, A> {
+    /// The RNG used to explore is seeded with `seed`, so that the agent's choices are reproducible,
+    /// e.g. derive it from the run's seed (`Manager::seed`) and the agent's id.
+    pub fn new(discount: f64, explore: f64, learning_rate: f64, seed: u64) -> QLearnerParams<S, A> {
         QLearnerParams {
             discount: discount,
             explore: explore,
             learning_rate: learning_rate,
             prev: None,
-            q: HashMap::new(),
-            rng: rand::thread_rng(),
+            q: FnvHashMap::default(),
+            rng: seeded_rng(seed),
         }
     }
 }
@@ -50,23 +55,32 @@ pub trait QLearner {
 
     /// Choose the best or a random action.
     fn choose_action(&mut self, state: &Self::State) -> Self::Action {
+        let mut rng = self.params().rng.clone();
+        let action = self.choose_action_with(state, &mut rng);
+        self.params().rng = rng;
+        action
+    }
+
+    /// Choose the best or a random action, exploring with the specified RNG,
+    /// e.g. `updates.rng()` for an agent in a simulation.
+    fn choose_action_with<R: Rng>(&mut self, state: &Self::State, rng: &mut R) -> Self::Action {
         let reward = self.reward(state);
         let actions = self.actions(state);
         let mut params = self.params();
         let action = {
-            if params.rng.gen::<f64>() < params.explore {
-                rand::sample(&mut params.rng, actions.clone(), 1)[0].clone()
+            if rng.gen::<f64>() < params.explore {
+                rand::sample(rng, actions.clone(), 1)[0].clone()
             } else {
                 if !params.q.contains_key(state) {
                     // initialize actions_rewards
-                    let mut actions_rewards = HashMap::new();
+                    let mut actions_rewards = FnvHashMap::default();
                     for action in &actions {
                         actions_rewards.insert(action.clone(), 0.0);
                     }
                     params.q.insert(state.clone(), actions_rewards);
 
                     // dont know anything yet, choose random
-                    rand::sample(&mut params.rng, actions.clone(), 1)[0].clone()
+                    rand::sample(rng, actions.clone(), 1)[0].clone()
                 } else {
                     let actions_rewards = params.q.get(state).unwrap();
                     let (action, _) = actions_rewards.iter()
@@ -93,7 +107,9 @@ fn learn<S: State, A: Action>(params: &mut QLearnerParams<S, A>, state: &S, rewa
         Some(ref prev) => {
             let &(ref p_state, ref p_action) = prev;
             let val = {
-                let actions_values = params.q.entry(p_state.clone()).or_insert_with(HashMap::new);
+                let actions_values = params.q
+                    .entry(p_state.clone())
+                    .or_insert_with(FnvHashMap::default);
                 *actions_values.entry(p_action.clone()).or_insert(0.)
             };
             let best_next_val = match params.q.get(state) {
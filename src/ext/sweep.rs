@@ -0,0 +1,479 @@
+//! Run a simulation over a grid or list of parameter configurations,
+//! with several replicates of each, collecting recorded values into one table.
+//!
+//! Sweeps can be defined in code or loaded from YAML, e.g.:
+//!
+//! ```yaml
+//! steps: 100
+//! replicates: 10
+//! seed: 42
+//! workers: 4
+//! grid:
+//!   opinion_shift_proportion: [0.1, 0.2, 0.5]
+//!   n_people: 1000
+//! ```
+//!
+//! For an example see `examples/sweep.rs`.
+
+use std::io::Write;
+use std::fs::File;
+use std::path::Path;
+use std::thread;
+use std::ops::Index;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::btree_map::{self, BTreeMap};
+use yaml_rust::Yaml;
+use hash::mix;
+use sim::Simulation;
+use backend::Backend;
+use compute::{Manager, Population, run_workers, shutdown_workers};
+use stop::RunSummary;
+use record::{Value, csv_field};
+use error::{DjinnError, Result};
+
+static BAD_VALUE: Yaml = Yaml::BadValue;
+
+/// Parameter values for one configuration of a simulation, by name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Params {
+    values: BTreeMap<String, Yaml>,
+}
+
+impl Params {
+    pub fn new() -> Params {
+        Params::default()
+    }
+
+    /// Set a parameter's value.
+    pub fn set(&mut self, name: &str, value: Yaml) {
+        self.values.insert(name.to_owned(), value);
+    }
+
+    /// Iterate over the parameters, by name.
+    pub fn iter(&self) -> btree_map::Iter<String, Yaml> {
+        self.values.iter()
+    }
+}
+
+impl<'a> Index<&'a str> for Params {
+    type Output = Yaml;
+
+    /// Missing parameters are `Yaml::BadValue`, as with `Yaml` itself.
+    fn index(&self, name: &str) -> &Yaml {
+        self.values.get(name).unwrap_or(&BAD_VALUE)
+    }
+}
+
+/// Every combination of the specified parameter values.
+pub fn grid(axes: Vec<(&str, Vec<Yaml>)>) -> Vec<Params> {
+    let mut configs = vec![Params::new()];
+    for (name, values) in axes {
+        let mut expanded = Vec::with_capacity(configs.len() * values.len());
+        for params in &configs {
+            for value in &values {
+                let mut params = params.clone();
+                params.set(name, value.clone());
+                expanded.push(params);
+            }
+        }
+        configs = expanded;
+    }
+    configs
+}
+
+/// One run of a sweep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRun {
+    pub params: Params,
+    pub replicate: usize,
+    pub seed: u64,
+}
+
+/// Values recorded at one step of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub params: Params,
+    pub replicate: usize,
+    pub step: usize,
+    pub values: Vec<(String, Value)>,
+}
+
+/// The outcome of a sweep.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Results {
+    /// Each run, with a summary of how it went.
+    pub runs: Vec<(SweepRun, RunSummary)>,
+
+    /// Recorded values across all runs.
+    pub records: Vec<Record>,
+}
+
+/// Render a parameter value for a table.
+fn yaml_to_string(value: &Yaml) -> String {
+    match *value {
+        Yaml::Real(ref s) |
+        Yaml::String(ref s) => s.clone(),
+        Yaml::Integer(i) => i.to_string(),
+        Yaml::Boolean(b) => b.to_string(),
+        Yaml::Null | Yaml::BadValue => String::new(),
+        ref other => format!("{:?}", other),
+    }
+}
+
+impl Results {
+    /// Write the records as CSV, with a column for each parameter,
+    /// the replicate, the step, and each reported value.
+    pub fn write_csv<W: Write>(&self, mut w: W) -> Result<()> {
+        let mut params: Vec<&String> = Vec::new();
+        let mut names: Vec<&String> = Vec::new();
+        for record in &self.records {
+            for (name, _) in record.params.iter() {
+                if !params.contains(&name) {
+                    params.push(name);
+                }
+            }
+            for &(ref name, _) in &record.values {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        params.sort();
+
+        let mut header: Vec<String> = params.iter().map(|p| csv_field(p)).collect();
+        header.push("replicate".to_owned());
+        header.push("step".to_owned());
+        header.extend(names.iter().map(|n| csv_field(n)));
+        writeln!(w, "{}", header.join(","))?;
+
+        for record in &self.records {
+            let mut row: Vec<String> = params.iter()
+                .map(|p| csv_field(&yaml_to_string(&record.params[p.as_str()])))
+                .collect();
+            row.push(record.replicate.to_string());
+            row.push(record.step.to_string());
+            for name in &names {
+                let value = record.values.iter().find(|&&(ref n, _)| n == *name);
+                row.push(match value {
+                    Some(&(_, ref v)) => csv_field(&v.to_string()),
+                    None => String::new(),
+                });
+            }
+            writeln!(w, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Save the records to a CSV file.
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_csv(File::create(path)?)
+    }
+}
+
+/// Runs a simulation over a set of parameter configurations.
+///
+/// Each configuration is run `replicates` times. Replicate `r` of each configuration
+/// uses the same seed (derived from the sweep's seed), so configurations are compared
+/// under the same random draws.
+pub struct Sweep<S: Simulation, B: Backend> {
+    configs: Vec<Params>,
+    n_steps: usize,
+    replicates: usize,
+    seed: u64,
+    n_workers: usize,
+    build: Arc<Fn(&Params) -> S + Send + Sync>,
+    setup: Arc<Fn(&Params, &mut Manager<S, B>) -> Result<S::World> + Send + Sync>,
+    recorders: Vec<(usize,
+                    Arc<Fn(usize, &Population<S, B>) -> Result<Vec<(String, Value)>> + Send + Sync>)>,
+}
+
+impl<S: Simulation, B: Backend> Clone for Sweep<S, B> {
+    fn clone(&self) -> Sweep<S, B> {
+        Sweep {
+            configs: self.configs.clone(),
+            n_steps: self.n_steps,
+            replicates: self.replicates,
+            seed: self.seed,
+            n_workers: self.n_workers,
+            build: self.build.clone(),
+            setup: self.setup.clone(),
+            recorders: self.recorders.clone(),
+        }
+    }
+}
+
+/// Read a non-negative integer from a YAML config, if it's there.
+fn yaml_count(conf: &Yaml, key: &str) -> Result<Option<usize>> {
+    match conf[key] {
+        Yaml::BadValue => Ok(None),
+        Yaml::Integer(i) if i >= 0 => Ok(Some(i as usize)),
+        _ => Err(DjinnError::Config(format!("`{}` must be a non-negative integer", key))),
+    }
+}
+
+impl<S: Simulation + 'static, B: Backend + 'static> Sweep<S, B> {
+    /// Create a sweep of `n_steps` runs.
+    ///
+    /// For each configuration, `build` creates the simulation. For each run,
+    /// `setup` prepares the manager, e.g. spawning the initial population
+    /// and registering stop conditions, and returns the initial world.
+    pub fn new<F, G>(n_steps: usize, build: F, setup: G) -> Sweep<S, B>
+        where F: Fn(&Params) -> S + Send + Sync + 'static,
+              G: Fn(&Params, &mut Manager<S, B>) -> Result<S::World> + Send + Sync + 'static
+    {
+        Sweep {
+            configs: Vec::new(),
+            n_steps: n_steps,
+            replicates: 1,
+            seed: 0,
+            n_workers: 1,
+            build: Arc::new(build),
+            setup: Arc::new(setup),
+            recorders: Vec::new(),
+        }
+    }
+
+    /// Add a configuration.
+    /// If no configurations are added, the simulation is run with no parameters.
+    pub fn add_config(&mut self, params: Params) {
+        self.configs.push(params);
+    }
+
+    /// Add every combination of the specified parameter values.
+    pub fn add_grid(&mut self, axes: Vec<(&str, Vec<Yaml>)>) {
+        self.configs.extend(grid(axes));
+    }
+
+    /// Load a sweep from YAML, e.g. as loaded with `ext::yaml::load_from_yaml`.
+    ///
+    /// Recognized keys are `steps`, `replicates`, `seed`, `workers`,
+    /// `grid` (a map of parameters to a value or list of values to combine),
+    /// and `configs` (a list of maps of parameters to values).
+    pub fn load_yaml(&mut self, conf: &Yaml) -> Result<()> {
+        if let Some(n_steps) = yaml_count(conf, "steps")? {
+            self.n_steps = n_steps;
+        }
+        if let Some(replicates) = yaml_count(conf, "replicates")? {
+            self.replicates = replicates;
+        }
+        if let Some(seed) = yaml_count(conf, "seed")? {
+            self.seed = seed as u64;
+        }
+        if let Some(n_workers) = yaml_count(conf, "workers")? {
+            self.n_workers = n_workers;
+        }
+
+        match conf["grid"] {
+            Yaml::Hash(ref axes) => {
+                let mut grid_axes = Vec::with_capacity(axes.len());
+                for (name, values) in axes {
+                    let name = match name.as_str() {
+                        Some(name) => name,
+                        None => return Err(DjinnError::Config("parameter names must be strings".to_owned())),
+                    };
+                    let values = match *values {
+                        Yaml::Array(ref values) => values.clone(),
+                        ref value => vec![value.clone()],
+                    };
+                    grid_axes.push((name, values));
+                }
+                self.add_grid(grid_axes);
+            }
+            Yaml::BadValue => (),
+            _ => return Err(DjinnError::Config("`grid` must be a map".to_owned())),
+        }
+
+        match conf["configs"] {
+            Yaml::Array(ref configs) => {
+                for config in configs {
+                    let config = match config.as_hash() {
+                        Some(config) => config,
+                        None => return Err(DjinnError::Config("`configs` must be a list of maps".to_owned())),
+                    };
+                    let mut params = Params::new();
+                    for (name, value) in config {
+                        match name.as_str() {
+                            Some(name) => params.set(name, value.clone()),
+                            None => return Err(DjinnError::Config("parameter names must be strings".to_owned())),
+                        }
+                    }
+                    self.add_config(params);
+                }
+            }
+            Yaml::BadValue => (),
+            _ => return Err(DjinnError::Config("`configs` must be a list".to_owned())),
+        }
+        Ok(())
+    }
+
+    /// Set how many times each configuration is run. Defaults to 1.
+    pub fn set_replicates(&mut self, replicates: usize) {
+        self.replicates = replicates;
+    }
+
+    /// Set the seed replicates' seeds are derived from. Defaults to 0.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Set how many local workers each pool runs. Defaults to 1.
+    pub fn set_workers(&mut self, n_workers: usize) {
+        self.n_workers = n_workers;
+    }
+
+    /// Register a recorder function to be called every `n_steps` of every run.
+    /// It returns named values to record for the step.
+    pub fn register_recorder<F>(&mut self, n_steps: usize, func: F) -> ()
+        where F: Fn(usize, &Population<S, B>) -> Result<Vec<(String, Value)>> + Send + Sync + 'static
+    {
+        self.recorders.push((n_steps, Arc::new(func)));
+    }
+
+    /// The configurations to run.
+    fn configs(&self) -> Vec<Params> {
+        if self.configs.is_empty() {
+            vec![Params::new()]
+        } else {
+            self.configs.clone()
+        }
+    }
+
+    /// List the runs in the sweep.
+    pub fn runs(&self) -> Vec<SweepRun> {
+        let mut runs = Vec::new();
+        for params in self.configs() {
+            for replicate in 0..self.replicates {
+                runs.push(SweepRun {
+                    params: params.clone(),
+                    replicate: replicate,
+                    seed: mix(self.seed, replicate as u64),
+                });
+            }
+        }
+        runs
+    }
+
+    /// Execute all runs, blocking until they're done.
+    ///
+    /// Each backend connection is used for a separate pool of local workers,
+    /// and pools run configurations in parallel. Pools must not share keys,
+    /// e.g. use a separate `MemoryBackend` or Redis database for each.
+    pub fn run(&self, pools: Vec<B>) -> Result<Results> {
+        if pools.is_empty() {
+            return Err(DjinnError::Config("a sweep needs at least one pool".to_owned()));
+        }
+        let sweep = Arc::new(self.clone());
+        let mut queue = sweep.configs();
+        queue.reverse();
+        let queue = Arc::new(Mutex::new(queue));
+        let results = Arc::new(Mutex::new(Results::default()));
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let pool_ts: Vec<thread::JoinHandle<Result<()>>> = pools.into_iter()
+            .map(|conn| {
+                let sweep = sweep.clone();
+                let queue = queue.clone();
+                let results = results.clone();
+                let failed = failed.clone();
+                thread::spawn(move || {
+                    let res = sweep.run_pool(conn, &queue, &results, &failed);
+                    if res.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    res
+                })
+            })
+            .collect();
+
+        let mut res = Ok(());
+        for t in pool_ts {
+            let pool_res = match t.join() {
+                Ok(pool_res) => pool_res,
+                Err(_) => Err(DjinnError::Protocol("sweep pool panicked".to_owned())),
+            };
+            if res.is_ok() {
+                res = pool_res;
+            }
+        }
+        res?;
+        let results = results.lock()?;
+        Ok(results.clone())
+    }
+
+    /// Run configurations from the queue until it's empty (or another pool fails).
+    fn run_pool(&self,
+                conn: B,
+                queue: &Mutex<Vec<Params>>,
+                results: &Mutex<Results>,
+                failed: &AtomicBool)
+                -> Result<()> {
+        loop {
+            if failed.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let params = match queue.lock()?.pop() {
+                Some(params) => params,
+                None => return Ok(()),
+            };
+            let sim = (self.build)(&params);
+
+            // workers are tied to a simulation, so each configuration gets its own,
+            // but they're kept up across its replicates
+            let workers_t = {
+                let conn = conn.clone();
+                let sim = sim.clone();
+                let n_workers = self.n_workers;
+                thread::spawn(move || run_workers(conn, sim, n_workers))
+            };
+            let res = self.run_replicates(&conn, &params, &sim, results);
+            let shutdown = shutdown_workers(&conn);
+            let workers_res = match workers_t.join() {
+                Ok(workers_res) => workers_res,
+                Err(_) => Err(DjinnError::Protocol("sweep workers panicked".to_owned())),
+            };
+            res?;
+            shutdown?;
+            workers_res?;
+        }
+    }
+
+    fn run_replicates(&self,
+                      conn: &B,
+                      params: &Params,
+                      sim: &S,
+                      results: &Mutex<Results>)
+                      -> Result<()> {
+        for replicate in 0..self.replicates {
+            let seed = mix(self.seed, replicate as u64);
+            let mut manager = Manager::new(conn.clone(), sim.clone())?;
+            manager.set_seed(seed);
+            let world = (self.setup)(params, &mut manager)?;
+
+            for &(interval, ref recorder) in &self.recorders {
+                let recorder = recorder.clone();
+                manager.register_recorder(interval, move |step, pop| recorder(step, pop));
+            }
+            let summary = manager.run(sim.clone(), world, self.n_steps)?;
+
+            // collect everything recorded,
+            // including by recorders registered in `setup`
+            let mut results = results.lock()?;
+            for row in manager.records()? {
+                results.records.push(Record {
+                    params: params.clone(),
+                    replicate: replicate,
+                    step: row.step,
+                    values: row.values,
+                });
+            }
+            let run = SweepRun {
+                params: params.clone(),
+                replicate: replicate,
+                seed: seed,
+            };
+            results.runs.push((run, summary));
+        }
+        Ok(())
+    }
+}
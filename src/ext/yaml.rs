@@ -4,15 +4,17 @@ use std::io::Read;
 use std::fs::File;
 use std::path::Path;
 use yaml_rust::{YamlLoader, Yaml};
+use error::{DjinnError, Result};
 
 /// Easily load a YAML file by filename.
-pub fn load_from_yaml(fname: &str) -> Yaml {
-    let path = Path::new(fname);
-    let mut file = File::open(&path).unwrap();
+pub fn load_from_yaml<P: AsRef<Path>>(fname: P) -> Result<Yaml> {
     let mut s = String::new();
-    file.read_to_string(&mut s).unwrap();
+    File::open(fname)?.read_to_string(&mut s)?;
 
     // just return the first yaml doc
-    let mut docs = YamlLoader::load_from_str(&s).unwrap();
-    docs.remove(0)
+    let mut docs = YamlLoader::load_from_str(&s).map_err(|err| DjinnError::Config(format!("{}", err)))?;
+    if docs.is_empty() {
+        return Err(DjinnError::Config("YAML file has no documents".to_owned()));
+    }
+    Ok(docs.remove(0))
 }
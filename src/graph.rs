@@ -0,0 +1,369 @@
+use rand::Rng;
+use fnv::FnvHashSet;
+use sim::Simulation;
+use backend::{Backend, BackendOp};
+use compute::{Population, Updates, member, from_member};
+use error::{DjinnError, Result};
+
+/// A network of directed, weighted edges between agents,
+/// stored in the backend alongside the population.
+///
+/// In an undirected graph, adding or removing an edge does so in both directions.
+/// Changes made from `decide` (e.g. `queue_add_edge`) are applied in the update phase,
+/// like other updates.
+///
+/// As with a `Grid`, the graph itself is just a description,
+/// so it can be kept in the simulation and queried through the `Population`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graph {
+    name: String,
+    directed: bool,
+}
+
+impl Graph {
+    pub fn directed(name: &str) -> Graph {
+        Graph {
+            name: name.to_owned(),
+            directed: true,
+        }
+    }
+
+    pub fn undirected(name: &str) -> Graph {
+        Graph {
+            name: name.to_owned(),
+            directed: false,
+        }
+    }
+
+    fn out_key(&self, id: u64) -> String {
+        format!("graph:{}:out:{}", self.name, id)
+    }
+
+    fn in_key(&self, id: u64) -> String {
+        format!("graph:{}:in:{}", self.name, id)
+    }
+
+    fn weight_key(&self, from: u64, to: u64) -> String {
+        format!("graph:{}:w:{}:{}", self.name, from, to)
+    }
+
+    /// The backend writes for adding or removing an edge.
+    fn edge_ops(&self, from: u64, to: u64, weight: Option<f64>) -> Vec<BackendOp> {
+        let mut ops = Vec::new();
+        let mut edges = vec![(from, to)];
+        if !self.directed && from != to {
+            edges.push((to, from));
+        }
+        for (from, to) in edges {
+            match weight {
+                Some(weight) => {
+                    ops.push(BackendOp::Add(self.out_key(from), member(to)));
+                    ops.push(BackendOp::Add(self.in_key(to), member(from)));
+                    ops.push(BackendOp::Set(self.weight_key(from, to), member(weight)));
+                }
+                None => {
+                    ops.push(BackendOp::Remove(self.out_key(from), member(to)));
+                    ops.push(BackendOp::Remove(self.in_key(to), member(from)));
+                    ops.push(BackendOp::Del(self.weight_key(from, to)));
+                }
+            }
+        }
+        ops
+    }
+
+    /// The backend writes for removing all of an agent's edges.
+    fn node_ops<S: Simulation, B: Backend>(&self,
+                                           population: &Population<S, B>,
+                                           id: u64)
+                                           -> Result<Vec<BackendOp>> {
+        let mut ops = Vec::new();
+        for to in self.neighbors(population, id)? {
+            ops.extend(self.edge_ops(id, to, None));
+        }
+        for from in self.predecessors(population, id)? {
+            ops.extend(self.edge_ops(from, id, None));
+        }
+        Ok(ops)
+    }
+
+    fn ids<B: Backend>(&self, conn: &B, key: &str) -> Result<Vec<u64>> {
+        let mut ids = conn.smembers(key)?
+            .into_iter()
+            .map(from_member)
+            .collect::<Result<Vec<u64>>>()?;
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Get the ids of the agents an agent has edges to, sorted.
+    pub fn neighbors<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<Vec<u64>> {
+        self.ids(&population.conn, &self.out_key(id))
+    }
+
+    /// Get the ids of the agents that have edges to an agent, sorted.
+    pub fn predecessors<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<Vec<u64>> {
+        self.ids(&population.conn, &self.in_key(id))
+    }
+
+    /// Get an agent's edges, as `(neighbor id, weight)`, sorted by neighbor id.
+    pub fn edges<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<Vec<(u64, f64)>> {
+        let neighbors = self.neighbors(population, id)?;
+        let keys: Vec<String> = neighbors.iter().map(|to| self.weight_key(id, *to)).collect();
+        neighbors.into_iter()
+            .zip(population.conn.get_multiple(&keys)?)
+            .map(|(to, weight)| Ok((to, self.parse_weight(id, to, weight)?)))
+            .collect()
+    }
+
+    /// Get the weight of the edge between two agents, if there is one.
+    pub fn weight<S: Simulation, B: Backend>(&self, population: &Population<S, B>, from: u64, to: u64) -> Result<Option<f64>> {
+        match population.conn.get(&self.weight_key(from, to))? {
+            Some(weight) => Ok(Some(self.parse_weight(from, to, Some(weight))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Decode a stored edge weight.
+    fn parse_weight(&self, from: u64, to: u64, data: Option<Vec<u8>>) -> Result<f64> {
+        let weight = data.and_then(|data| String::from_utf8(data).ok()).and_then(|s| s.parse().ok());
+        weight.ok_or_else(|| {
+            DjinnError::Serialization(format!("missing or malformed weight for edge {} -> {} in graph {}",
+                                              from,
+                                              to,
+                                              self.name))
+        })
+    }
+
+    /// The number of edges from an agent.
+    pub fn degree<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<usize> {
+        population.conn.scard(&self.out_key(id))
+    }
+
+    /// Add an edge (or set its weight, if it exists), e.g. when setting up the graph.
+    pub fn add_edge<S: Simulation, B: Backend>(&self,
+                                               population: &Population<S, B>,
+                                               from: u64,
+                                               to: u64,
+                                               weight: f64)
+                                               -> Result<()> {
+        population.conn.apply(self.edge_ops(from, to, Some(weight)))
+    }
+
+    /// Add edges with the same weight, e.g. ones made by a generator like `erdos_renyi`,
+    /// in one request.
+    pub fn add_edges<S: Simulation, B: Backend>(&self,
+                                                population: &Population<S, B>,
+                                                edges: &[(u64, u64)],
+                                                weight: f64)
+                                                -> Result<()> {
+        let ops = edges.iter().flat_map(|&(from, to)| self.edge_ops(from, to, Some(weight))).collect();
+        population.conn.apply(ops)
+    }
+
+    /// Remove an edge.
+    pub fn remove_edge<S: Simulation, B: Backend>(&self, population: &Population<S, B>, from: u64, to: u64) -> Result<()> {
+        population.conn.apply(self.edge_ops(from, to, None))
+    }
+
+    /// Remove all edges to and from an agent.
+    pub fn remove_node<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<()> {
+        let ops = self.node_ops(population, id)?;
+        population.conn.apply(ops)
+    }
+
+    /// Queue adding an edge (or setting its weight, if it exists).
+    pub fn queue_add_edge<S: Simulation>(&self, updates: &mut Updates<S>, from: u64, to: u64, weight: f64) {
+        for op in self.edge_ops(from, to, Some(weight)) {
+            updates.queue_op(op);
+        }
+    }
+
+    /// Queue removing an edge.
+    pub fn queue_remove_edge<S: Simulation>(&self, updates: &mut Updates<S>, from: u64, to: u64) {
+        for op in self.edge_ops(from, to, None) {
+            updates.queue_op(op);
+        }
+    }
+
+    /// Queue removing all edges to and from an agent, e.g. for an agent that's being killed.
+    pub fn queue_remove_node<S: Simulation, B: Backend>(&self,
+                                                        population: &Population<S, B>,
+                                                        updates: &mut Updates<S>,
+                                                        id: u64)
+                                                        -> Result<()> {
+        for op in self.node_ops(population, id)? {
+            updates.queue_op(op);
+        }
+        Ok(())
+    }
+}
+
+/// Generate an Erdős–Rényi random graph over agents,
+/// where each pair of agents has an edge with probability `p`.
+///
+/// Each pair is listed once, so add these to an undirected graph
+/// (or to a directed graph, for edges in one direction).
+pub fn erdos_renyi<R: Rng>(ids: &[u64], p: f64, rng: &mut R) -> Vec<(u64, u64)> {
+    let mut edges = Vec::new();
+    for (i, &from) in ids.iter().enumerate() {
+        for &to in &ids[i + 1..] {
+            if rng.gen::<f64>() < p {
+                edges.push((from, to));
+            }
+        }
+    }
+    edges
+}
+
+/// Generate a Watts–Strogatz small-world graph over agents: a ring where each agent
+/// has edges to its `k` nearest neighbors (`k / 2` on each side), each of which
+/// is rewired to a random agent with probability `beta`.
+///
+/// Each pair is listed once, as with `erdos_renyi`. `k` must be less than the number of agents.
+pub fn watts_strogatz<R: Rng>(ids: &[u64], k: usize, beta: f64, rng: &mut R) -> Result<Vec<(u64, u64)>> {
+    let n = ids.len();
+    if k >= n {
+        return Err(DjinnError::Config(format!("watts_strogatz needs k ({}) to be less than the number of agents ({})",
+                                              k,
+                                              n)));
+    }
+
+    // pairs with edges, smallest first
+    let pair = |a: usize, b: usize| if a < b { (a, b) } else { (b, a) };
+    let mut connected: FnvHashSet<(usize, usize)> = FnvHashSet::default();
+    let mut edges: Vec<(usize, usize)> = Vec::new();
+    for i in 0..n {
+        for j in 1..k / 2 + 1 {
+            let to = (i + j) % n;
+            if to != i && connected.insert(pair(i, to)) {
+                edges.push((i, to));
+            }
+        }
+    }
+    let mut degrees = vec![0; n];
+    for &(a, b) in &connected {
+        degrees[a] += 1;
+        degrees[b] += 1;
+    }
+
+    for edge in edges.iter_mut() {
+        let (from, old) = *edge;
+
+        // only rewire if there's somewhere to rewire to
+        if rng.gen::<f64>() >= beta || degrees[from] + 1 >= n {
+            continue;
+        }
+        loop {
+            let to = rng.gen_range(0, n);
+            if to != from && !connected.contains(&pair(from, to)) {
+                connected.remove(&pair(from, old));
+                connected.insert(pair(from, to));
+                degrees[old] -= 1;
+                degrees[to] += 1;
+                *edge = (from, to);
+                break;
+            }
+        }
+    }
+    Ok(edges.into_iter().map(|(from, to)| (ids[from], ids[to])).collect())
+}
+
+/// Generate a Barabási–Albert scale-free graph over agents, by preferential attachment:
+/// each agent after the first `m` gets edges to `m` of the agents before it,
+/// chosen with probability proportional to their degree.
+///
+/// Each pair is listed once, as with `erdos_renyi`.
+pub fn barabasi_albert<R: Rng>(ids: &[u64], m: usize, rng: &mut R) -> Vec<(u64, u64)> {
+    let mut edges = Vec::new();
+    if m == 0 || ids.len() <= m {
+        return edges;
+    }
+
+    // agents appear in this once for each edge they have,
+    // so drawing from it is proportional to degree
+    let mut repeated: Vec<u64> = Vec::new();
+    let mut targets: Vec<u64> = ids[..m].to_vec();
+    for &from in &ids[m..] {
+        for &to in &targets {
+            edges.push((from, to));
+            repeated.push(to);
+            repeated.push(from);
+        }
+        targets.clear();
+        while targets.len() < m {
+            let to = *rng.choose(&repeated).unwrap();
+            if !targets.contains(&to) {
+                targets.push(to);
+            }
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::seeded_rng;
+
+    /// Check that edges have no self-loops or duplicates, in either direction.
+    fn assert_simple(edges: &[(u64, u64)]) {
+        let mut pairs = FnvHashSet::default();
+        for &(from, to) in edges {
+            assert!(from != to);
+            assert!(pairs.insert((from.min(to), from.max(to))));
+        }
+    }
+
+    #[test]
+    fn erdos_renyi_extremes() {
+        let ids: Vec<u64> = (0..20).collect();
+        let mut rng = seeded_rng(0);
+        assert!(erdos_renyi(&ids, 0., &mut rng).is_empty());
+        let edges = erdos_renyi(&ids, 1., &mut rng);
+        assert_eq!(edges.len(), 20 * 19 / 2);
+        assert_simple(&edges);
+    }
+
+    #[test]
+    fn watts_strogatz_ring() {
+        let ids: Vec<u64> = (0..20).collect();
+        let mut rng = seeded_rng(0);
+        let edges = watts_strogatz(&ids, 4, 0., &mut rng).unwrap();
+        assert_eq!(edges.len(), 20 * 4 / 2);
+        assert_simple(&edges);
+        for &id in &ids {
+            assert_eq!(edges.iter().filter(|&&(a, b)| a == id || b == id).count(), 4);
+        }
+    }
+
+    #[test]
+    fn watts_strogatz_rewiring_keeps_edge_count() {
+        let ids: Vec<u64> = (0..50).collect();
+        let mut rng = seeded_rng(1);
+        let edges = watts_strogatz(&ids, 6, 0.5, &mut rng).unwrap();
+        assert_eq!(edges.len(), 50 * 6 / 2);
+        assert_simple(&edges);
+    }
+
+    #[test]
+    fn watts_strogatz_small_rings() {
+        let mut rng = seeded_rng(3);
+        assert!(watts_strogatz(&[0, 1, 2], 3, 0., &mut rng).is_err());
+        assert!(watts_strogatz(&[], 0, 0., &mut rng).is_err());
+
+        // every agent is already connected to every other
+        let edges = watts_strogatz(&[0, 1, 2, 3, 4], 4, 0.5, &mut rng).unwrap();
+        assert_eq!(edges.len(), 5 * 4 / 2);
+        assert_simple(&edges);
+    }
+
+    #[test]
+    fn barabasi_albert_attaches_m_edges() {
+        let ids: Vec<u64> = (0..50).collect();
+        let mut rng = seeded_rng(2);
+        let edges = barabasi_albert(&ids, 3, &mut rng);
+        assert_eq!(edges.len(), (50 - 3) * 3);
+        assert_simple(&edges);
+        assert!(barabasi_albert(&ids[..3], 3, &mut rng).is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+use std::marker::PhantomData;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ser::Codec;
+use sim::Simulation;
+use backend::{Backend, BackendOp};
+use compute::{Population, Updates, member, from_member};
+use error::{DjinnError, Result};
+
+/// A cell position on a grid.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
+pub struct Pos {
+    pub x: usize,
+    pub y: usize,
+}
+
+/// What happens at the edges of a grid.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    /// Positions off the edge don't exist.
+    Bounded,
+
+    /// Positions off one edge wrap around to the opposite edge.
+    Toroidal,
+}
+
+/// Which cells around a position count as its neighbors.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Neighborhood {
+    /// Cells within `radius` steps in any direction, including diagonally.
+    Moore,
+
+    /// Cells within `radius` steps horizontally and vertically, i.e. within Manhattan distance.
+    VonNeumann,
+}
+
+/// A 2D grid of cells, each with properties of type `C` and a set of occupying agents,
+/// stored in the backend alongside the population.
+///
+/// Moving an agent only touches the two cells involved,
+/// rather than re-writing the whole grid as part of the world.
+/// Changes made from `decide` (e.g. `queue_move`) are applied in the update phase,
+/// like other updates. Conflicting changes to the same cell in a step
+/// (e.g. two agents setting its properties) are applied in no particular order.
+///
+/// The grid itself is just a description of the space,
+/// so it can be kept in the simulation and queried through the `Population`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid<C: Serialize + DeserializeOwned> {
+    name: String,
+    pub width: usize,
+    pub height: usize,
+    pub topology: Topology,
+    cell: PhantomData<C>,
+}
+
+impl<C: Serialize + DeserializeOwned> Grid<C> {
+    pub fn new(name: &str, width: usize, height: usize, topology: Topology) -> Grid<C> {
+        Grid {
+            name: name.to_owned(),
+            width: width,
+            height: height,
+            topology: topology,
+            cell: PhantomData,
+        }
+    }
+
+    fn cell_key(&self, pos: Pos) -> String {
+        format!("grid:{}:cell:{}:{}", self.name, pos.x, pos.y)
+    }
+
+    fn occupants_key(&self, pos: Pos) -> String {
+        format!("grid:{}:occ:{}:{}", self.name, pos.x, pos.y)
+    }
+
+    /// Get the position at an offset from another, wrapping around
+    /// the edges if the grid is toroidal. Returns `None` if it's off the grid.
+    pub fn offset(&self, pos: Pos, dx: isize, dy: isize) -> Option<Pos> {
+        let (w, h) = (self.width as isize, self.height as isize);
+        let (x, y) = (pos.x as isize + dx, pos.y as isize + dy);
+        match self.topology {
+            Topology::Bounded => {
+                if x < 0 || y < 0 || x >= w || y >= h {
+                    None
+                } else {
+                    Some(Pos {
+                        x: x as usize,
+                        y: y as usize,
+                    })
+                }
+            }
+            Topology::Toroidal => {
+                Some(Pos {
+                    x: (((x % w) + w) % w) as usize,
+                    y: (((y % h) + h) % h) as usize,
+                })
+            }
+        }
+    }
+
+    /// The positions around a position (not including itself), in row-major order.
+    /// On a small toroidal grid, cells the neighborhood wraps onto twice are only included once.
+    pub fn neighborhood(&self, pos: Pos, neighborhood: Neighborhood, radius: usize) -> Vec<Pos> {
+        let r = radius as isize;
+        let mut positions = Vec::new();
+        for dy in -r..r + 1 {
+            for dx in -r..r + 1 {
+                if (dx == 0 && dy == 0) ||
+                   (neighborhood == Neighborhood::VonNeumann && dx.abs() + dy.abs() > r) {
+                    continue;
+                }
+                if let Some(p) = self.offset(pos, dx, dy) {
+                    if p != pos && !positions.contains(&p) {
+                        positions.push(p);
+                    }
+                }
+            }
+        }
+        positions
+    }
+
+    /// Where agents at a position should be placed in the id space
+    /// (see `Updates::spawn_placed`), so that agents are partitioned
+    /// across workers by region: bands of rows by default,
+    /// or blocks with a `RegionPartitioner`.
+    pub fn placement(&self, pos: Pos) -> f64 {
+        (pos.y * self.width + pos.x) as f64 / (self.width * self.height) as f64
+    }
+
+    fn check(&self, pos: Pos) -> Result<()> {
+        if pos.x >= self.width || pos.y >= self.height {
+            Err(DjinnError::Config(format!("({}, {}) is off the `{}` grid", pos.x, pos.y, self.name)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get the properties of a cell.
+    pub fn cell<S: Simulation, B: Backend>(&self, population: &Population<S, B>, pos: Pos) -> Result<Option<C>> {
+        match population.conn.get(&self.cell_key(pos))? {
+            Some(data) => Ok(Some(S::Codec::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the properties of multiple cells, in the order of the positions.
+    pub fn cells<S: Simulation, B: Backend>(&self,
+                                            population: &Population<S, B>,
+                                            positions: &[Pos])
+                                            -> Result<Vec<Option<C>>> {
+        let keys: Vec<String> = positions.iter().map(|pos| self.cell_key(*pos)).collect();
+        population.conn
+            .get_multiple(&keys)?
+            .into_iter()
+            .map(|data| match data {
+                Some(data) => S::Codec::decode(&data).map(Some),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    /// Get the ids of the agents occupying a cell, sorted.
+    pub fn occupants<S: Simulation, B: Backend>(&self, population: &Population<S, B>, pos: Pos) -> Result<Vec<u64>> {
+        let mut ids = population.conn
+            .smembers(&self.occupants_key(pos))?
+            .into_iter()
+            .map(from_member)
+            .collect::<Result<Vec<u64>>>()?;
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Get the ids of the agents occupying the cells around a position
+    /// (not including the position itself).
+    pub fn neighbors<S: Simulation, B: Backend>(&self,
+                                                population: &Population<S, B>,
+                                                pos: Pos,
+                                                neighborhood: Neighborhood,
+                                                radius: usize)
+                                                -> Result<Vec<u64>> {
+        let keys: Vec<String> = self.neighborhood(pos, neighborhood, radius)
+            .into_iter()
+            .map(|p| self.occupants_key(p))
+            .collect();
+        let mut ids = Vec::new();
+        for members in population.conn.smembers_multiple(&keys)? {
+            let mut occupants = members.into_iter().map(from_member).collect::<Result<Vec<u64>>>()?;
+            occupants.sort();
+            ids.extend(occupants);
+        }
+        Ok(ids)
+    }
+
+    /// Set the properties of a cell, e.g. when setting up the grid.
+    pub fn set_cell<S: Simulation, B: Backend>(&self, population: &Population<S, B>, pos: Pos, cell: &C) -> Result<()> {
+        self.check(pos)?;
+        population.conn.set(&self.cell_key(pos), S::Codec::encode(cell)?)
+    }
+
+    /// Put an agent in a cell, e.g. when setting up the grid.
+    pub fn place<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64, pos: Pos) -> Result<()> {
+        self.check(pos)?;
+        population.conn.sadd(&self.occupants_key(pos), vec![member(id)])
+    }
+
+    /// Take an agent out of a cell.
+    pub fn remove<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64, pos: Pos) -> Result<()> {
+        population.conn.srem(&self.occupants_key(pos), vec![member(id)])
+    }
+
+    /// Queue setting the properties of a cell.
+    pub fn queue_set_cell<S: Simulation>(&self, updates: &mut Updates<S>, pos: Pos, cell: &C) -> Result<()> {
+        self.check(pos)?;
+        updates.queue_op(BackendOp::Set(self.cell_key(pos), S::Codec::encode(cell)?));
+        Ok(())
+    }
+
+    /// Queue putting an agent in a cell, e.g. for a newly spawned agent.
+    pub fn queue_place<S: Simulation>(&self, updates: &mut Updates<S>, id: u64, pos: Pos) -> Result<()> {
+        self.check(pos)?;
+        updates.queue_op(BackendOp::Add(self.occupants_key(pos), member(id)));
+        Ok(())
+    }
+
+    /// Queue moving an agent from one cell to another.
+    pub fn queue_move<S: Simulation>(&self, updates: &mut Updates<S>, id: u64, from: Pos, to: Pos) -> Result<()> {
+        self.check(to)?;
+        updates.queue_op(BackendOp::Remove(self.occupants_key(from), member(id)));
+        updates.queue_op(BackendOp::Add(self.occupants_key(to), member(id)));
+        Ok(())
+    }
+
+    /// Queue taking an agent out of a cell, e.g. for an agent that's being killed.
+    pub fn queue_remove<S: Simulation>(&self, updates: &mut Updates<S>, id: u64, pos: Pos) {
+        updates.queue_op(BackendOp::Remove(self.occupants_key(pos), member(id)));
+    }
+}
@@ -1,5 +1,8 @@
+use rand::{SeedableRng, XorShiftRng};
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
+use std::sync::Arc;
+use partition::Partitioner;
 
 pub fn hash<T: Hash>(t: &T) -> u64 {
     let mut s = DefaultHasher::new();
@@ -7,25 +10,53 @@ pub fn hash<T: Hash>(t: &T) -> u64 {
     s.finish()
 }
 
+/// Deterministically mix a value into a seed (splitmix64),
+/// e.g. to derive per-step or per-agent seeds from a run seed.
+pub fn mix(seed: u64, val: u64) -> u64 {
+    let mut z = seed.wrapping_add(val.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Create a fast RNG from a seed.
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    let a = mix(seed, 0);
+    let b = mix(seed, 1);
+    // xorshift seeds can't be all zeros
+    XorShiftRng::from_seed([(a >> 32) as u32, a as u32, (b >> 32) as u32, (b as u32) | 1])
+}
+
+/// How many of an id's high bits are set by its placement (see `place`).
+pub const PLACEMENT_BITS: u32 = 20;
+
+/// Move an id to a part of the id space, given as a fraction in `[0, 1]`,
+/// keeping its low bits. With the default `RangePartitioner`, workers are assigned
+/// contiguous ranges of the id space, so ids with nearby placements end up on the same worker.
+pub fn place(id: u64, placement: f64) -> u64 {
+    let n_buckets = 1u64 << PLACEMENT_BITS;
+    let bucket = (placement.max(0.) * n_buckets as f64) as u64;
+    let shift = 64 - PLACEMENT_BITS;
+    (bucket.min(n_buckets - 1) << shift) | (id & ((1u64 << shift) - 1))
+}
+
+/// Assigns agents to workers, using the simulation's `Partitioner`.
 #[derive(Clone)]
 pub struct WHasher {
     n_workers: usize,
+    partitioner: Arc<Partitioner>,
 }
 
 impl WHasher {
-    pub fn new(n_workers: usize) -> WHasher {
-        WHasher { n_workers: n_workers }
+    pub fn new(n_workers: usize, partitioner: Arc<Partitioner>) -> WHasher {
+        WHasher {
+            n_workers: n_workers,
+            partitioner: partitioner,
+        }
     }
 
     /// Hashes an id to a value in the range of `n_workers`.
     pub fn hash(&self, id: u64) -> usize {
-        let w = self.n_workers as f64;
-        let p = (id as f64) / (u64::max_value() as f64);
-        for j in 0..self.n_workers {
-            if (j as f64) / w <= p && ((j + 1) as f64) / w > p {
-                return j;
-            }
-        }
-        self.n_workers - 1
+        self.partitioner.partition(id, self.n_workers)
     }
 }
@@ -0,0 +1,115 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::mpsc::{self, Sender, RecvTimeoutError};
+use backend::Backend;
+use error::Result;
+use fnv::FnvHashMap;
+
+/// How often workers send heartbeats.
+pub const HEARTBEAT_INTERVAL_MS: u64 = 500;
+
+/// How often the manager checks heartbeats.
+const CHECK_INTERVAL_MS: u64 = 250;
+
+/// What the manager should do when a worker dies or gets stuck.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailurePolicy {
+    /// Abort the run with an error.
+    Abort,
+
+    /// Hand the failed worker's agents over to the surviving workers and carry on.
+    ///
+    /// Agent states are already in the backend, so nothing is lost between steps.
+    /// Updates the failed worker was processing when it failed may be lost or,
+    /// for a stuck worker that later recovers, applied twice.
+    Reassign,
+}
+
+fn heartbeat_key(uid: &str) -> String {
+    format!("heartbeat:{}", uid)
+}
+
+/// A background thread which periodically lets the manager know a worker is alive.
+///
+/// Each heartbeat is a counter rather than a timestamp,
+/// so the manager doesn't rely on clocks being in sync across machines.
+pub struct Heartbeat {
+    stop: Sender<()>,
+    t: thread::JoinHandle<()>,
+}
+
+impl Heartbeat {
+    pub fn start<B: Backend + 'static>(conn: B, uid: String) -> Heartbeat {
+        let (tx, rx) = mpsc::channel();
+        let t = thread::spawn(move || {
+            let key = heartbeat_key(&uid);
+            let interval = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+            let mut beat: u64 = 0;
+            loop {
+                // if the backend is unreachable, just keep trying;
+                // the manager will notice the missing heartbeats
+                let _ = conn.set(&key, beat.to_string().into_bytes());
+                beat = beat.wrapping_add(1);
+                match rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+            let _ = conn.del(&key);
+        });
+        Heartbeat { stop: tx, t: t }
+    }
+
+    /// Stop sending heartbeats.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.t.join();
+    }
+}
+
+/// Tracks worker heartbeats on the manager's side.
+pub struct HeartbeatMonitor {
+    timeout: Duration,
+    last_check: Option<Instant>,
+    last_beats: FnvHashMap<String, (Option<Vec<u8>>, Instant)>,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(timeout: Duration) -> HeartbeatMonitor {
+        HeartbeatMonitor {
+            timeout: timeout,
+            last_check: None,
+            last_beats: FnvHashMap::default(),
+        }
+    }
+
+    /// Check on the specified workers (by uid),
+    /// returning those that haven't sent a heartbeat within the timeout.
+    pub fn check<B: Backend>(&mut self, conn: &B, uids: &[String]) -> Result<Vec<String>> {
+        let now = Instant::now();
+        if let Some(last_check) = self.last_check {
+            if now.duration_since(last_check) < Duration::from_millis(CHECK_INTERVAL_MS) {
+                return Ok(Vec::new());
+            }
+        }
+        self.last_check = Some(now);
+
+        let keys: Vec<String> = uids.iter().map(|uid| heartbeat_key(uid)).collect();
+        let beats = conn.get_multiple(&keys)?;
+        let mut dead = Vec::new();
+        for (uid, beat) in uids.iter().zip(beats) {
+            let last = self.last_beats.entry(uid.clone()).or_insert_with(|| (None, now));
+            if last.0 != beat {
+                *last = (beat, now);
+            } else if now.duration_since(last.1) > self.timeout {
+                dead.push(uid.clone());
+            }
+        }
+        Ok(dead)
+    }
+
+    /// Stop tracking a worker.
+    pub fn forget(&mut self, uid: &str) {
+        self.last_beats.remove(uid);
+    }
+}
@@ -4,6 +4,7 @@
 //! along with some extensions for common behaviors.
 //! - a distributed architecture mediated by Redis which allows computationally-intense
 //! agents to be processed in parallel across multiple processes and/or multiple machines.
+//! Simulations can also be run in a single process, without Redis, using the `MemoryBackend`.
 //! - utilities for logging and emitting events during the simulation, e.g. to a websocket
 //! frontend.
 //!
@@ -15,16 +16,46 @@ extern crate uuid;
 extern crate rand;
 extern crate redis;
 extern crate yaml_rust;
-extern crate rmp_serialize;
-extern crate rustc_serialize;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate bincode;
+extern crate rmp_serde;
 
 mod sim;
 mod ser;
 mod hash;
 mod event;
+mod error;
+mod backend;
+mod heartbeat;
+mod checkpoint;
+mod stop;
+mod record;
+mod migrate;
+mod schedule;
 mod compute;
+mod grid;
+mod space;
+mod graph;
+mod partition;
 pub mod ext;
 
 pub use event::WebSocketServer;
+pub use error::{DjinnError, Result};
+pub use heartbeat::FailurePolicy;
+pub use checkpoint::Checkpoint;
+pub use stop::{RunSummary, StopReason};
+pub use record::{Value, Row, RecordFormat};
 pub use sim::{Agent, Update, State, Simulation};
-pub use compute::{Population, Manager, Updates, Worker, Redis, run, run_workers};
+pub use ser::{Codec, MessagePack, Bincode, Json};
+pub use migrate::Migrations;
+pub use schedule::Schedule;
+pub use hash::seeded_rng;
+pub use backend::{Backend, BackendOp, Subscription, MemoryBackend, MemorySubscription};
+pub use grid::{Grid, Pos, Topology, Neighborhood};
+pub use space::{Space, Point};
+pub use graph::{Graph, erdos_renyi, watts_strogatz, barabasi_albert};
+pub use partition::{Partitioner, RangePartitioner, HashPartitioner, RegionPartitioner, CommunityPartitioner};
+pub use compute::{Population, Manager, Updates, Worker, PhaseReport, run, run_workers, retire_workers};
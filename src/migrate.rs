@@ -0,0 +1,208 @@
+use std::marker::PhantomData;
+use std::collections::BTreeMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ser::Codec;
+use sim::Simulation;
+use error::{DjinnError, Result};
+
+/// Marks a versioned agent state. States without it are version 0: they were
+/// stored before versioning, or while no migrations were registered.
+///
+/// It starts with 0xc1, which MessagePack never uses and which can't start
+/// UTF-8 text, so no MessagePack or JSON state is mistaken for a versioned one.
+/// Bincode isn't self-describing, so an unversioned bincode state could in principle
+/// start with these bytes; its "version" would then almost certainly be out of range,
+/// which `Migrations::decode` reports as an error rather than misreading the state.
+const ENVELOPE_MAGIC: &'static [u8] = b"\xc1djinnv";
+
+/// A registry of migrations for stored agent states, from each version to the next.
+///
+/// Agent states are stored along with the version they were encoded at,
+/// which is the version after the latest registered migration.
+/// If there are none, states are version 0 and stored as plain codec output,
+/// so e.g. `Json` states stay readable text.
+/// States stored at older versions are migrated when they're decoded,
+/// so populations and checkpoints saved by older iterations of a model can still be loaded.
+pub struct Migrations<S: Simulation> {
+    steps: BTreeMap<u32, Box<Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync>>,
+    simulation: PhantomData<S>,
+}
+
+impl<S: Simulation> Migrations<S> {
+    pub fn new() -> Migrations<S> {
+        Migrations {
+            steps: BTreeMap::new(),
+            simulation: PhantomData,
+        }
+    }
+
+    /// Register a migration of states from version `from` to `from + 1`.
+    ///
+    /// `Old` is the state type as it was at version `from`, and `New` as it is at `from + 1`
+    /// (the current `Simulation::State`, if this is the latest migration).
+    pub fn register<Old, New, F>(&mut self, from: u32, func: F)
+        where Old: DeserializeOwned,
+              New: Serialize,
+              F: Fn(Old) -> New + Send + Sync + 'static
+    {
+        self.steps.insert(from,
+                          Box::new(move |data| {
+                              let old: Old = S::Codec::decode(data)?;
+                              S::Codec::encode(&func(old))
+                          }));
+    }
+
+    /// The current state version.
+    pub fn version(&self) -> u32 {
+        self.steps.keys().next_back().map_or(0, |from| from + 1)
+    }
+
+    /// Encode a state, tagged with the current version (unless that's 0).
+    pub fn encode(&self, state: &S::State) -> Result<Vec<u8>> {
+        let version = self.version();
+        if version == 0 {
+            return S::Codec::encode(state);
+        }
+        let mut data = ENVELOPE_MAGIC.to_vec();
+        data.extend_from_slice(&[(version >> 24) as u8, (version >> 16) as u8, (version >> 8) as u8, version as u8]);
+        data.extend(S::Codec::encode(state)?);
+        Ok(data)
+    }
+
+    /// Decode a state, migrating it to the current version if necessary.
+    pub fn decode(&self, data: &[u8]) -> Result<S::State> {
+        let header = ENVELOPE_MAGIC.len() + 4;
+        let (mut version, payload) = if data.len() >= header && data.starts_with(ENVELOPE_MAGIC) {
+            let version = data[ENVELOPE_MAGIC.len()..header]
+                .iter()
+                .fold(0, |version, &byte| (version << 8) | byte as u32);
+            (version, &data[header..])
+        } else {
+            (0, data)
+        };
+
+        let current = self.version();
+        if version > current {
+            return Err(DjinnError::Serialization(format!("state version {} is newer than the current version {}",
+                                                         version,
+                                                         current)));
+        }
+        if version == current {
+            return S::Codec::decode(payload);
+        }
+
+        let mut migrated = payload.to_vec();
+        while version < current {
+            match self.steps.get(&version) {
+                Some(step) => migrated = step(&migrated)?,
+                None => {
+                    return Err(DjinnError::Serialization(format!("no migration from state version {}", version)))
+                }
+            }
+            version += 1;
+        }
+        S::Codec::decode(&migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sim::Agent;
+    use backend::Backend;
+    use compute::{Population, Updates};
+    use ser::MessagePack;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct V0 {
+        name: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct V1 {
+        name: String,
+        age: u32,
+    }
+
+    #[derive(Clone)]
+    struct Sim;
+
+    impl Simulation for Sim {
+        type State = V1;
+        type World = ();
+        type Update = ();
+        type Codec = MessagePack;
+
+        fn decide<B: Backend>(&self, _: &Agent<V1>, _: &(), _: &Population<Self, B>, _: &mut Updates<Self>) {}
+
+        fn update(&self, _: &mut V1, _: Vec<()>) -> bool {
+            false
+        }
+    }
+
+    fn migrations() -> Migrations<Sim> {
+        let mut migrations = Migrations::new();
+        migrations.register(0, |old: V0| {
+            V1 {
+                name: old.name,
+                age: 0,
+            }
+        });
+        migrations
+    }
+
+    #[test]
+    fn round_trip() {
+        let migrations = migrations();
+        assert_eq!(migrations.version(), 1);
+        let state = V1 {
+            name: "a".to_owned(),
+            age: 3,
+        };
+        let data = migrations.encode(&state).unwrap();
+        assert!(data.starts_with(ENVELOPE_MAGIC));
+        assert_eq!(migrations.decode(&data).unwrap(), state);
+    }
+
+    #[test]
+    fn unversioned_states_are_plain() {
+        let state = V1 {
+            name: "a".to_owned(),
+            age: 3,
+        };
+        let data = Migrations::<Sim>::new().encode(&state).unwrap();
+        assert_eq!(data, MessagePack::encode(&state).unwrap());
+        assert_eq!(Migrations::<Sim>::new().decode(&data).unwrap(), state);
+    }
+
+    #[test]
+    fn migrates_unversioned_states() {
+        let data = MessagePack::encode(&V0 { name: "a".to_owned() }).unwrap();
+        let state = migrations().decode(&data).unwrap();
+        assert_eq!(state,
+                   V1 {
+                       name: "a".to_owned(),
+                       age: 0,
+                   });
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let data = migrations()
+            .encode(&V1 {
+                name: "a".to_owned(),
+                age: 3,
+            })
+            .unwrap();
+        assert!(Migrations::<Sim>::new().decode(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_migrations() {
+        let mut migrations = Migrations::<Sim>::new();
+        migrations.register(1, |old: V1| old);
+        let data = MessagePack::encode(&V0 { name: "a".to_owned() }).unwrap();
+        assert!(migrations.decode(&data).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+use rand::Rng;
+use fnv::FnvHashMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use hash::{mix, seeded_rng, PLACEMENT_BITS};
+use grid::Grid;
+use graph::Graph;
+use sim::Simulation;
+use backend::Backend;
+use compute::Population;
+use error::Result;
+
+/// The most rounds of label propagation `CommunityPartitioner::detect` runs.
+const MAX_LABEL_ROUNDS: usize = 20;
+
+/// Decides which worker each agent belongs to.
+///
+/// Agents are routed by id alone (e.g. when an update is queued for one),
+/// so this must give the same answer for an id everywhere, for the whole run.
+/// Putting agents that interact on the same worker means fewer updates
+/// have to go through the backend.
+pub trait Partitioner: Send + Sync {
+    /// The worker (out of `n_workers`) an agent belongs to.
+    fn partition(&self, id: u64, n_workers: usize) -> usize;
+
+    /// Which of `n_workers` workers each of `n_slots` slots (numbered by `partition`)
+    /// starts a run on, so that each worker's slots hold agents that are close together.
+    ///
+    /// By default, each worker gets an equal contiguous run of slots.
+    fn assign(&self, n_slots: usize, n_workers: usize) -> Vec<usize> {
+        (0..n_slots).map(|slot| slot * n_workers / n_slots).collect()
+    }
+}
+
+/// Splits the id space into equal contiguous ranges, one per worker. This is the default.
+///
+/// Agents spawned with `spawn_placed` at nearby placements end up on the same worker,
+/// e.g. agents in the same band of rows of a `Grid`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangePartitioner;
+
+impl Partitioner for RangePartitioner {
+    fn partition(&self, id: u64, n_workers: usize) -> usize {
+        let w = n_workers as f64;
+        let p = (id as f64) / (u64::max_value() as f64);
+        for j in 0..n_workers {
+            if (j as f64) / w <= p && ((j + 1) as f64) / w > p {
+                return j;
+            }
+        }
+        n_workers - 1
+    }
+}
+
+/// Scatters agents across workers by a hash of their id, ignoring any placement,
+/// e.g. to spread out agents that would otherwise crowd onto a few workers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashPartitioner;
+
+impl Partitioner for HashPartitioner {
+    fn partition(&self, id: u64, n_workers: usize) -> usize {
+        (mix(id, 0) % n_workers as u64) as usize
+    }
+}
+
+/// Splits a grid into rectangular blocks of cells, one per worker,
+/// for agents spawned with `spawn_placed` at `Grid::placement`.
+///
+/// Blocks have shorter borders than the bands of rows `RangePartitioner` gives,
+/// so fewer of an agent's neighbors are on other workers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegionPartitioner {
+    width: usize,
+    height: usize,
+}
+
+impl RegionPartitioner {
+    pub fn new(width: usize, height: usize) -> RegionPartitioner {
+        RegionPartitioner {
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn for_grid<C: Serialize + DeserializeOwned>(grid: &Grid<C>) -> RegionPartitioner {
+        RegionPartitioner::new(grid.width, grid.height)
+    }
+
+    /// The number of columns and rows of blocks to split the grid into,
+    /// keeping blocks as square as possible.
+    fn blocks(&self, n_workers: usize) -> (usize, usize) {
+        let ideal = (n_workers as f64 * self.width as f64 / self.height as f64).sqrt();
+        let cols = (1..n_workers + 1)
+            .filter(|cols| n_workers % cols == 0)
+            .min_by_key(|&cols| ((cols as f64 - ideal).abs() * 1000.) as u64)
+            .unwrap_or(1);
+        (cols, n_workers / cols)
+    }
+}
+
+impl Partitioner for RegionPartitioner {
+    fn partition(&self, id: u64, n_workers: usize) -> usize {
+        let n_cells = self.width * self.height;
+        if n_cells == 0 {
+            return RangePartitioner.partition(id, n_workers);
+        }
+
+        // recover the cell from the id's placement
+        let bucket = id >> (64 - PLACEMENT_BITS);
+        let p = (bucket as f64 + 0.5) / (1u64 << PLACEMENT_BITS) as f64;
+        let cell = ((p * n_cells as f64) as usize).min(n_cells - 1);
+        let (x, y) = (cell % self.width, cell / self.width);
+
+        let (cols, rows) = self.blocks(n_workers);
+        (y * rows / self.height) * cols + x * cols / self.width
+    }
+
+    /// Blocks are numbered by row, so contiguous runs of them would give workers bands
+    /// of the grid. Instead, each worker gets a rectangular tile of blocks.
+    fn assign(&self, n_slots: usize, n_workers: usize) -> Vec<usize> {
+        let (cols, rows) = self.blocks(n_slots);
+        let (tile_cols, tile_rows) = self.blocks(n_workers);
+        (0..n_slots)
+            .map(|slot| {
+                let (x, y) = (slot % cols, slot / cols);
+                (y * tile_rows / rows) * tile_cols + x * tile_cols / cols
+            })
+            .collect()
+    }
+}
+
+/// Assigns agents to workers by community, e.g. from a graph partitioning tool
+/// like METIS, or detected from a `Graph` with `detect`.
+///
+/// Agents are ordered by community and split into equal contiguous runs, one per worker,
+/// so members of a community end up on the same worker where possible.
+/// Agents without a community (e.g. ones spawned later) fall back to `RangePartitioner`.
+#[derive(Debug, Clone)]
+pub struct CommunityPartitioner {
+    ranks: Arc<FnvHashMap<u64, f64>>,
+}
+
+impl CommunityPartitioner {
+    /// Create a partitioner from `(agent id, community)` pairs.
+    pub fn new(mut communities: Vec<(u64, u64)>) -> CommunityPartitioner {
+        communities.sort_by_key(|&(id, community)| (community, id));
+        let n = communities.len() as f64;
+        let ranks = communities.into_iter()
+            .enumerate()
+            .map(|(rank, (id, _))| (id, rank as f64 / n))
+            .collect();
+        CommunityPartitioner { ranks: Arc::new(ranks) }
+    }
+
+    /// Detect communities of agents in a graph by label propagation:
+    /// each agent repeatedly takes on the label with the most (weighted) edges among its neighbors.
+    /// The same seed gives the same communities, so each worker can run this for itself.
+    pub fn detect<S: Simulation, B: Backend>(graph: &Graph,
+                                             population: &Population<S, B>,
+                                             ids: &[u64],
+                                             seed: u64)
+                                             -> Result<CommunityPartitioner> {
+        let mut edges = FnvHashMap::default();
+        for &id in ids {
+            edges.insert(id, graph.edges(population, id)?);
+        }
+
+        let mut labels: FnvHashMap<u64, u64> = ids.iter().map(|&id| (id, id)).collect();
+        let mut order = ids.to_vec();
+        order.sort();
+        let mut rng = seeded_rng(seed);
+        for _ in 0..MAX_LABEL_ROUNDS {
+            rng.shuffle(&mut order);
+            let mut changed = false;
+            for id in &order {
+                let mut weights: FnvHashMap<u64, f64> = FnvHashMap::default();
+                for &(other, weight) in &edges[id] {
+                    if let Some(&label) = labels.get(&other) {
+                        *weights.entry(label).or_insert(0.) += weight;
+                    }
+                }
+
+                // the heaviest label, breaking ties by the smallest
+                let mut best: Option<(u64, f64)> = None;
+                for (label, weight) in weights {
+                    best = match best {
+                        Some((l, w)) if w > weight || (w == weight && l < label) => Some((l, w)),
+                        _ => Some((label, weight)),
+                    };
+                }
+                if let Some((label, _)) = best {
+                    if labels[id] != label {
+                        labels.insert(*id, label);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(CommunityPartitioner::new(labels.into_iter().collect()))
+    }
+}
+
+impl Partitioner for CommunityPartitioner {
+    fn partition(&self, id: u64, n_workers: usize) -> usize {
+        match self.ranks.get(&id) {
+            Some(rank) => ((rank * n_workers as f64) as usize).min(n_workers - 1),
+            None => RangePartitioner.partition(id, n_workers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::place;
+
+    #[test]
+    fn range_splits_ids_in_order() {
+        let p = RangePartitioner;
+        assert_eq!(p.partition(0, 4), 0);
+        assert_eq!(p.partition(u64::max_value(), 4), 3);
+        assert_eq!(p.partition(u64::max_value() / 3, 2), 0);
+        assert_eq!(p.partition(u64::max_value() / 3 * 2, 2), 1);
+
+        let ids: Vec<u64> = (0..100).map(|i| i * (u64::max_value() / 100)).collect();
+        let workers: Vec<usize> = ids.iter().map(|&id| p.partition(id, 7)).collect();
+        assert!(workers.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn hash_stays_in_range() {
+        let p = HashPartitioner;
+        let mut counts = vec![0; 5];
+        for id in 0..1000 {
+            let worker = p.partition(id, 5);
+            assert_eq!(worker, p.partition(id, 5));
+            counts[worker] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 100));
+    }
+
+    #[test]
+    fn region_splits_grid_into_blocks() {
+        let (width, height) = (4, 4);
+        let p = RegionPartitioner::new(width, height);
+        let worker = |x: usize, y: usize| {
+            let placement = (y * width + x) as f64 / (width * height) as f64;
+            p.partition(place(12345, placement), 4)
+        };
+        assert_eq!(worker(0, 0), 0);
+        assert_eq!(worker(1, 1), 0);
+        assert_eq!(worker(3, 0), 1);
+        assert_eq!(worker(0, 3), 2);
+        assert_eq!(worker(3, 3), 3);
+        assert_eq!(worker(2, 2), 3);
+    }
+
+    #[test]
+    fn region_gives_workers_square_tiles() {
+        let (width, height) = (16, 16);
+        let p = RegionPartitioner::new(width, height);
+        let (n_workers, n_slots) = (4, 32);
+        let assignment = p.assign(n_slots, n_workers);
+        let mut extents = vec![(width, height, 0, 0, 0); n_workers];
+        for y in 0..height {
+            for x in 0..width {
+                let placement = (y * width + x) as f64 / (width * height) as f64;
+                let worker = assignment[p.partition(place(12345, placement), n_slots)];
+                let e = &mut extents[worker];
+                *e = (e.0.min(x), e.1.min(y), e.2.max(x + 1), e.3.max(y + 1), e.4 + 1);
+            }
+        }
+        for (x0, y0, x1, y1, n_cells) in extents {
+            assert_eq!((x1 - x0, y1 - y0, n_cells), (8, 8, 64));
+        }
+    }
+
+    #[test]
+    fn default_assignment_is_contiguous() {
+        assert_eq!(RangePartitioner.assign(6, 3), vec![0, 0, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn community_keeps_communities_together() {
+        let p = CommunityPartitioner::new(vec![(1, 0), (2, 1), (3, 0), (4, 1)]);
+        assert_eq!(p.partition(1, 2), p.partition(3, 2));
+        assert_eq!(p.partition(2, 2), p.partition(4, 2));
+        assert!(p.partition(1, 2) != p.partition(2, 2));
+
+        // agents without a community fall back to ranges
+        assert_eq!(p.partition(u64::max_value(), 2), 1);
+    }
+}
@@ -0,0 +1,572 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use serde::Serialize;
+use serde_json::{self, Map, Value as Json};
+use compute::Population;
+use sim::Simulation;
+use backend::Backend;
+use error::{DjinnError, Result};
+
+/// A recorded value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Category(String),
+
+    /// No value this step, e.g. the mean of an empty index.
+    /// Written as an empty CSV field or a JSON `null`.
+    Missing,
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Value {
+        Value::Number(val)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(val: usize) -> Value {
+        Value::Number(val as f64)
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Value {
+        Value::Category(val)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(val: &str) -> Value {
+        Value::Category(val.to_owned())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Number(val) => write!(f, "{}", val),
+            Value::Category(ref val) => write!(f, "{}", val),
+            Value::Missing => Ok(()),
+        }
+    }
+}
+
+/// The values recorded at a step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub step: usize,
+    pub values: Vec<(String, Value)>,
+}
+
+/// File formats for recordings and population snapshots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// A column for the step and for each value.
+    Csv,
+
+    /// A JSON object per step, keyed by value name.
+    JsonLines,
+}
+
+/// Produces named values to record at a step.
+pub enum Recorder<S: Simulation, B: Backend> {
+    /// The number of agents in an index, as `name`.
+    Count(String, String),
+
+    /// The mean of a function of the states of agents in an index, as `name`.
+    /// `Value::Missing` if the index is empty.
+    Mean(String, String, Box<Fn(&S::State) -> f64 + Send>),
+
+    /// Counts of a function of the states of agents in an index,
+    /// binned by the specified edges, as `name[lo,hi)`.
+    /// The last bin includes its upper edge; values outside the edges aren't counted.
+    Histogram(String, String, Vec<f64>, Box<Fn(&S::State) -> f64 + Send>),
+
+    /// A user-defined recorder.
+    Custom(Box<Fn(usize, &Population<S, B>) -> Result<Vec<(String, Value)>> + Send>),
+}
+
+impl<S: Simulation, B: Backend> Recorder<S, B> {
+    pub fn record(&self, step: usize, population: &Population<S, B>) -> Result<Vec<(String, Value)>> {
+        match *self {
+            Recorder::Count(ref name, ref index) => {
+                Ok(vec![(name.clone(), population.count_index(index)?.into())])
+            }
+            Recorder::Mean(ref name, ref index, ref func) => {
+                let agents = population.lookup(index)?;
+                if agents.is_empty() {
+                    return Ok(vec![(name.clone(), Value::Missing)]);
+                }
+                let total: f64 = agents.iter().map(|a| func(&a.state)).sum();
+                Ok(vec![(name.clone(), (total / agents.len() as f64).into())])
+            }
+            Recorder::Histogram(ref name, ref index, ref edges, ref func) => {
+                let n_bins = if edges.len() > 1 { edges.len() - 1 } else { 0 };
+                let mut counts = vec![0; n_bins];
+                for agent in population.lookup(index)? {
+                    let val = func(&agent.state);
+                    for bin in 0..n_bins {
+                        let last = bin == n_bins - 1;
+                        if val >= edges[bin] && (val < edges[bin + 1] || last && val == edges[bin + 1]) {
+                            counts[bin] += 1;
+                            break;
+                        }
+                    }
+                }
+                Ok(counts.into_iter()
+                    .enumerate()
+                    .map(|(bin, count)| {
+                        let close = if bin == n_bins - 1 { "]" } else { ")" };
+                        (format!("{}[{},{}{}", name, edges[bin], edges[bin + 1], close), count.into())
+                    })
+                    .collect())
+            }
+            Recorder::Custom(ref func) => func(step, population),
+        }
+    }
+}
+
+/// Quote a CSV field, if necessary.
+pub fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Split a CSV line into its (unquoted) fields.
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(field.split_off(0)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn csv_header(columns: &[String]) -> String {
+    let mut header = vec!["step".to_owned()];
+    header.extend(columns.iter().map(|c| csv_field(c)));
+    header.join(",")
+}
+
+fn csv_row(columns: &[String], row: &Row) -> String {
+    let mut fields = vec![row.step.to_string()];
+    for column in columns {
+        let val = row.values.iter().find(|&&(ref name, _)| name == column);
+        fields.push(match val {
+            Some(&(_, ref val)) => csv_field(&val.to_string()),
+            None => String::new(),
+        });
+    }
+    fields.join(",")
+}
+
+/// Encode a value as JSON.
+pub fn to_json<T: Serialize>(val: &T) -> Result<Json> {
+    serde_json::to_value(val).map_err(|err| DjinnError::Serialization(format!("{}", err)))
+}
+
+/// Render a JSON value as a CSV field, leaving strings unquoted.
+fn json_field(val: &Json) -> String {
+    match *val {
+        Json::String(ref val) => csv_field(val),
+        ref val => csv_field(&val.to_string()),
+    }
+}
+
+/// Flatten a state into CSV fields. The fields of objects (e.g. structs,
+/// or the struct variants of enums, which are nested under the variant's name)
+/// become `parent.child` columns; anything else gets one `state` column.
+fn csv_fields(state: Json) -> Map<String, Json> {
+    let mut flat = Map::new();
+    match state {
+        Json::Object(fields) => flatten("", fields, &mut flat),
+        state => {
+            flat.insert("state".to_owned(), state);
+        }
+    }
+    flat
+}
+
+fn flatten(prefix: &str, fields: Map<String, Json>, flat: &mut Map<String, Json>) {
+    for (name, val) in fields {
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match val {
+            Json::Object(fields) => flatten(&name, fields, flat),
+            val => {
+                flat.insert(name, val);
+            }
+        }
+    }
+}
+
+/// Write agents as JSON-lines or CSV, as they come.
+pub struct AgentWriter<W: Write> {
+    w: W,
+    format: RecordFormat,
+
+    /// CSV columns, added up front or else fixed by the first agent written.
+    columns: Vec<String>,
+
+    /// Whether any agent has been written, i.e. whether the CSV header has been.
+    started: bool,
+}
+
+impl<W: Write> AgentWriter<W> {
+    pub fn new(w: W, format: RecordFormat) -> AgentWriter<W> {
+        AgentWriter {
+            w: w,
+            format: format,
+            columns: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Write the world, if the format has room for it (i.e. JSON-lines).
+    pub fn write_world<T: Serialize>(&mut self, world: &T) -> Result<()> {
+        if self.format == RecordFormat::JsonLines {
+            let mut obj = Map::new();
+            obj.insert("world".to_owned(), to_json(world)?);
+            writeln!(self.w, "{}", Json::Object(obj))?;
+        }
+        Ok(())
+    }
+
+    /// Add a state's fields to the CSV columns, before any agent is written,
+    /// e.g. so that states with different fields (like variants of an enum) each get theirs.
+    pub fn add_columns<T: Serialize>(&mut self, state: &T) -> Result<()> {
+        if self.started {
+            return Err(DjinnError::Protocol("CSV columns can't be added once agents are written".to_owned()));
+        }
+        for name in csv_fields(to_json(state)?).keys() {
+            if !self.columns.contains(name) {
+                self.columns.push(name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an agent. For CSV, each (flattened) field of the state gets its own column,
+    /// see `csv_fields`. Unless columns were added up front, they're fixed by the first agent written.
+    pub fn write_agent<T: Serialize>(&mut self, id: u64, state: &T) -> Result<()> {
+        match self.format {
+            RecordFormat::JsonLines => {
+                let mut obj = Map::new();
+                obj.insert("id".to_owned(), Json::from(id));
+                obj.insert("state".to_owned(), to_json(state)?);
+                writeln!(self.w, "{}", Json::Object(obj))?;
+            }
+            RecordFormat::Csv => {
+                if !self.started {
+                    if self.columns.is_empty() {
+                        self.add_columns(state)?;
+                    }
+                    let mut header = vec!["id".to_owned()];
+                    header.extend(self.columns.iter().map(|c| csv_field(c)));
+                    writeln!(self.w, "{}", header.join(","))?;
+                    self.started = true;
+                }
+                let fields = csv_fields(to_json(state)?);
+                if let Some(name) = fields.keys().find(|name| !self.columns.contains(name)) {
+                    return Err(DjinnError::Config(format!("`{}` is not a column of the CSV snapshot", name)));
+                }
+                let mut row = vec![id.to_string()];
+                row.extend(self.columns.iter().map(|c| fields.get(c).map_or(String::new(), json_field)));
+                writeln!(self.w, "{}", row.join(","))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.w.flush()?)
+    }
+}
+
+fn json_row(row: &Row) -> String {
+    let mut obj = Map::new();
+    obj.insert("step".to_owned(), Json::from(row.step));
+    for &(ref name, ref val) in &row.values {
+        let val = match *val {
+            Value::Number(val) => Json::from(val),
+            Value::Category(ref val) => Json::from(val.clone()),
+            Value::Missing => Json::Null,
+        };
+        obj.insert(name.clone(), val);
+    }
+    Json::Object(obj).to_string()
+}
+
+/// Write rows in the specified format.
+/// CSV columns are the names of all values across the rows, in order of appearance.
+pub fn write_rows<W: Write>(mut w: W, rows: &[Row], format: RecordFormat) -> Result<()> {
+    match format {
+        RecordFormat::Csv => {
+            let mut columns: Vec<String> = Vec::new();
+            for row in rows {
+                for &(ref name, _) in &row.values {
+                    if !columns.contains(name) {
+                        columns.push(name.clone());
+                    }
+                }
+            }
+            writeln!(w, "{}", csv_header(&columns))?;
+            for row in rows {
+                writeln!(w, "{}", csv_row(&columns, row))?;
+            }
+        }
+        RecordFormat::JsonLines => {
+            for row in rows {
+                writeln!(w, "{}", json_row(row))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The rows recorded over a run, optionally written out as they're recorded.
+pub struct Recording {
+    rows: Vec<Row>,
+    out: Option<(RecordFormat, BufWriter<File>)>,
+
+    /// CSV columns, fixed by the first row written.
+    columns: Option<Vec<String>>,
+
+    /// Values left out of the CSV output because they aren't columns of it.
+    dropped: Vec<String>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording {
+            rows: Vec::new(),
+            out: None,
+            columns: None,
+            dropped: Vec::new(),
+        }
+    }
+
+    /// Write rows to a file as they're recorded.
+    /// If `append`ing (e.g. when resuming a run), the file is assumed to already have a header.
+    pub fn stream_to(&mut self, path: &Path, format: RecordFormat, append: bool) -> Result<()> {
+        let file = if append {
+            OpenOptions::new().append(true).create(true).open(path)?
+        } else {
+            File::create(path)?
+        };
+        self.out = Some((format, BufWriter::new(file)));
+        self.columns = None;
+        if !append {
+            return Ok(());
+        }
+
+        // pick up the existing columns
+        if format == RecordFormat::Csv {
+            let mut contents = String::new();
+            File::open(path)?.read_to_string(&mut contents)?;
+            if let Some(line) = contents.lines().next() {
+                self.columns = Some(parse_csv_line(line).into_iter().skip(1).collect());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, row: Row) -> Result<()> {
+        if let Some((format, ref mut w)) = self.out {
+            match format {
+                RecordFormat::Csv => {
+                    if self.columns.is_none() {
+                        let columns: Vec<String> = row.values.iter().map(|&(ref name, _)| name.clone()).collect();
+                        writeln!(w, "{}", csv_header(&columns))?;
+                        self.columns = Some(columns);
+                    }
+                    if let Some(ref columns) = self.columns {
+                        // values that aren't columns are left out (but still kept in `rows`),
+                        // rather than failing the run partway through
+                        for &(ref name, _) in &row.values {
+                            if !columns.contains(name) && !self.dropped.contains(name) {
+                                eprintln!("warning: `{}` is not a column of the CSV recording, which is fixed \
+                                           by the first row, so it's left out of it",
+                                          name);
+                                self.dropped.push(name.clone());
+                            }
+                        }
+                        writeln!(w, "{}", csv_row(columns, &row))?;
+                    }
+                }
+                RecordFormat::JsonLines => writeln!(w, "{}", json_row(&row))?,
+            }
+            w.flush()?;
+        }
+        self.rows.push(row);
+        Ok(())
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Row> {
+        vec![Row {
+                 step: 0,
+                 values: vec![("count".to_owned(), Value::from(3usize)), ("mean".to_owned(), Value::Missing)],
+             },
+             Row {
+                 step: 1,
+                 values: vec![("count".to_owned(), Value::from(2usize)), ("weather".to_owned(), Value::from("a, b"))],
+             }]
+    }
+
+    #[test]
+    fn csv_rows_have_the_union_of_columns() {
+        let mut out = Vec::new();
+        write_rows(&mut out, &rows(), RecordFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "step,count,mean,weather\n0,3,,\n1,2,,\"a, b\"\n");
+    }
+
+    #[test]
+    fn json_rows() {
+        let mut out = Vec::new();
+        write_rows(&mut out, &rows(), RecordFormat::JsonLines).unwrap();
+        let lines: Vec<Json> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["count"], Json::from(3.));
+        assert_eq!(lines[0]["mean"], Json::Null);
+        assert_eq!(lines[1]["weather"], Json::from("a, b"));
+    }
+
+    #[test]
+    fn streamed_csv_leaves_out_new_values() {
+        let path = ::std::env::temp_dir().join("djinn_record_stream.csv");
+        let mut recording = Recording::new();
+        recording.stream_to(&path, RecordFormat::Csv, false).unwrap();
+        for row in rows() {
+            recording.push(row).unwrap();
+        }
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "step,count,mean\n0,3,\n1,2,\n");
+        assert_eq!(recording.rows(), &rows()[..]);
+    }
+
+    #[test]
+    fn csv_fields_round_trip() {
+        let fields = vec!["plain".to_owned(), "a, b".to_owned(), "say \"hi\"".to_owned()];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        assert_eq!(parse_csv_line(&line.join(",")), fields);
+    }
+
+    #[derive(Serialize)]
+    struct Person {
+        age: u32,
+        reach: u32,
+    }
+
+    #[test]
+    fn agents_as_csv_columns() {
+        let state = Person { age: 3, reach: 10 };
+        let mut out = Vec::new();
+        {
+            let mut writer = AgentWriter::new(&mut out, RecordFormat::Csv);
+            writer.write_agent(1, &state).unwrap();
+            writer.write_agent(2, &state).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "id,age,reach\n1,3,10\n2,3,10\n");
+    }
+
+    #[test]
+    fn agents_without_fields_get_a_state_column() {
+        let mut out = Vec::new();
+        {
+            let mut writer = AgentWriter::new(&mut out, RecordFormat::Csv);
+            writer.write_agent(1, &5u32).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "id,state\n1,5\n");
+    }
+
+    #[derive(Serialize)]
+    enum Kind {
+        Person { name: String },
+        Media { reach: u32 },
+        Nobody,
+    }
+
+    #[test]
+    fn agents_with_different_shapes() {
+        let agents = vec![Kind::Person { name: "a".to_owned() }, Kind::Media { reach: 10 }];
+        let mut out = Vec::new();
+        {
+            let mut writer = AgentWriter::new(&mut out, RecordFormat::Csv);
+            for state in &agents {
+                writer.add_columns(state).unwrap();
+            }
+            writer.write_agent(1, &agents[0]).unwrap();
+            writer.write_agent(2, &agents[1]).unwrap();
+            assert!(writer.add_columns(&Kind::Nobody).is_err());
+            assert!(writer.write_agent(3, &Kind::Nobody).is_err());
+            writer.flush().unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "id,Person.name,Media.reach\n1,a,\n2,,10\n");
+    }
+
+    #[derive(Serialize)]
+    struct Located {
+        pos: (u32, u32),
+        home: Home,
+    }
+
+    #[derive(Serialize)]
+    struct Home {
+        x: u32,
+        y: u32,
+    }
+
+    #[test]
+    fn nested_fields_are_flattened() {
+        let mut out = Vec::new();
+        {
+            let mut writer = AgentWriter::new(&mut out, RecordFormat::Csv);
+            writer.write_agent(1,
+                              &Located {
+                                  pos: (1, 2),
+                                  home: Home { x: 3, y: 4 },
+                              })
+                .unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "id,home.x,home.y,pos\n1,3,4,\"[1,2]\"\n");
+    }
+}
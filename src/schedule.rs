@@ -0,0 +1,108 @@
+use hash::mix;
+
+/// Salt for drawing whether an agent is active at a step,
+/// kept separate from the agent's own random draws.
+const ACTIVATION_SALT: u64 = 0x6163_7469_7661_7465;
+
+/// How agents are activated each step: which of them decide, in what order,
+/// and in what order their updates are applied.
+///
+/// Each worker runs its agents' decides in the schedule's order, but workers decide
+/// in parallel and agents only read states from before the step. So an agent
+/// never sees the effects of agents that decided before it within the step;
+/// the order matters for side effects of `decide` and for the order
+/// updates sent to an agent by different senders are applied in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Every agent decides every step, in order of id.
+    /// Updates are applied in order of their sender's id.
+    Synchronous,
+
+    /// Every agent decides every step, in an order shuffled each step
+    /// (from the run's seed). Updates are applied in that same order of senders.
+    ///
+    /// Agents still decide from the states at the start of the step, so an agent doesn't
+    /// see the effects of agents "before" it in the order. For that, use `Staged`.
+    Shuffled,
+
+    /// Each agent decides with this probability each step.
+    /// Active agents decide, and their updates are applied, in a shuffled order,
+    /// as with `Shuffled`.
+    RandomSubset(f64),
+
+    /// Agents decide in this many stages each step, according to `Simulation::stage`
+    /// (e.g. by agent type). The updates from each stage are applied
+    /// before the next stage decides. Updates are applied in order of their sender's id.
+    Staged(usize),
+
+    /// Agents only decide when something is due for them: when they're spawned,
+    /// when updates arrive for them (including ones queued with `Updates::queue_at`),
+    /// or when they wake up (see `Updates::sleep_until`). Rather than ticking through
+    /// every step, the clock skips ahead to the next step something is due at,
+    /// and the run ends when nothing is. Updates are applied in order of their sender's id.
+    DiscreteEvent,
+}
+
+impl Schedule {
+    /// The number of decide/update rounds per step.
+    pub fn n_stages(&self) -> usize {
+        match *self {
+            Schedule::Staged(n_stages) => n_stages,
+            _ => 1,
+        }
+    }
+
+    /// The rank agents decide in, and updates from a sender are ordered by,
+    /// given the sender's key for the step (see `Updates::rng`).
+    pub fn rank(&self, key: u64, sender: u64) -> u64 {
+        match *self {
+            Schedule::Shuffled |
+            Schedule::RandomSubset(_) => key,
+            Schedule::Synchronous |
+            Schedule::Staged(_) |
+            Schedule::DiscreteEvent => sender,
+        }
+    }
+
+    /// Whether an agent is active, given its key for the step.
+    pub fn is_active(&self, key: u64) -> bool {
+        match *self {
+            Schedule::RandomSubset(p) => (mix(key, ACTIVATION_SALT) as f64 / u64::max_value() as f64) < p,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everyone_is_active_by_default() {
+        for key in 0..100 {
+            assert!(Schedule::Synchronous.is_active(key));
+            assert!(Schedule::Shuffled.is_active(key));
+            assert!(Schedule::Staged(2).is_active(key));
+        }
+    }
+
+    #[test]
+    fn random_subset_samples_by_probability() {
+        let active = |p: f64| (0..10000).filter(|&key| Schedule::RandomSubset(p).is_active(key)).count();
+        assert_eq!(active(0.), 0);
+        assert_eq!(active(1.), 10000);
+        let half = active(0.5);
+        assert!(half > 4500 && half < 5500);
+
+        // the same key gives the same answer
+        assert_eq!(Schedule::RandomSubset(0.5).is_active(42),
+                   Schedule::RandomSubset(0.5).is_active(42));
+    }
+
+    #[test]
+    fn shuffled_schedules_rank_by_key() {
+        assert_eq!(Schedule::Synchronous.rank(7, 3), 3);
+        assert_eq!(Schedule::Shuffled.rank(7, 3), 7);
+        assert_eq!(Schedule::RandomSubset(0.5).rank(7, 3), 7);
+    }
+}
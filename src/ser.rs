@@ -1,17 +1,68 @@
-use std::io;
-use rmp_serialize::decode::Error;
-use rmp_serialize::{Encoder, Decoder};
-use rustc_serialize::{Encodable, Decodable};
-
-pub fn decode<R: Decodable>(inp: Vec<u8>) -> Result<R, Error> {
-    let mut decoder = Decoder::new(&inp[..]);
-    Decodable::decode(&mut decoder)
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use rmp_serde;
+use bincode;
+use serde_json;
+use error::{DjinnError, Result};
+
+/// Encodes values to and decodes values from the bytes stored in the backend.
+pub trait Codec: Send + Sync + 'static {
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T>;
+}
+
+/// MessagePack, a compact binary format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessagePack;
+
+/// Bincode, which is fast but not self-describing,
+/// so stored values can only be decoded into exactly the type they were encoded from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bincode;
+
+/// JSON, which is larger and slower but human-readable,
+/// e.g. for inspecting the backend while debugging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Json;
+
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec(data).map_err(|e| DjinnError::Serialization(format!("{}", e)))
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(data).map_err(|e| DjinnError::Serialization(format!("{}", e)))
+    }
+}
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+        bincode::serialize(data).map_err(|e| DjinnError::Serialization(format!("{}", e)))
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        bincode::deserialize(data).map_err(|e| DjinnError::Serialization(format!("{}", e)))
+    }
 }
 
-pub fn encode<R: Encodable>(data: R) -> Result<Vec<u8>, io::Error> {
-    let mut buf = Vec::<u8>::new();
-    match data.encode(&mut Encoder::new(&mut buf)) {
-        Ok(_) => Ok(buf),
-        Err(e) => Err(io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+impl Codec for Json {
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(data).map_err(|e| DjinnError::Serialization(format!("{}", e)))
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(|e| DjinnError::Serialization(format!("{}", e)))
     }
 }
+
+/// Decode djinn's own data (e.g. checkpoints), which is always MessagePack,
+/// whatever codec the simulation uses.
+pub fn decode<R: DeserializeOwned>(inp: Vec<u8>) -> Result<R> {
+    MessagePack::decode(&inp)
+}
+
+/// Encode djinn's own data (e.g. checkpoints), which is always MessagePack,
+/// whatever codec the simulation uses.
+pub fn encode<R: Serialize>(data: R) -> Result<Vec<u8>> {
+    MessagePack::encode(&data)
+}
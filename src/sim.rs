@@ -1,19 +1,27 @@
 use std::fmt::Debug;
-use compute::{Population, Redis, Updates};
-use rustc_serialize::{Decodable, Encodable};
+use std::sync::Arc;
+use compute::{Population, Updates};
+use backend::Backend;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ser::Codec;
+use migrate::Migrations;
+use schedule::Schedule;
+use partition::{Partitioner, RangePartitioner};
 
 /// The state that represents an `Agent`.
-pub trait State: Decodable + Encodable + Debug + Send + Sync + Clone + PartialEq {}
-impl<T> State for T where T: Decodable + Encodable + Debug + Send + Sync + Clone + PartialEq {}
+pub trait State: Serialize + DeserializeOwned + Debug + Send + Sync + Clone + PartialEq {}
+impl<T> State for T where T: Serialize + DeserializeOwned + Debug + Send + Sync + Clone + PartialEq {}
 
 /// An message that agents can queue for themselves or for others.
 pub trait Update
-    : Decodable + Encodable + Debug + Send + Sync + Clone + PartialEq {
+    : Serialize + DeserializeOwned + Debug + Send + Sync + Clone + PartialEq {
 }
-impl<T> Update for T where T: Decodable + Encodable + Debug + Send + Sync + Clone + PartialEq {}
+impl<T> Update for T where T: Serialize + DeserializeOwned + Debug + Send + Sync + Clone + PartialEq {}
 
 /// Struct containing a unique id and a state.
-#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(bound = "")]
 pub struct Agent<S: State> {
     pub id: u64,
     pub state: S,
@@ -32,27 +40,63 @@ pub trait Simulation: Sized + Send + Sync + Clone {
     type World: State;
     type Update: Update;
 
+    /// How states, the world, and updates are encoded in the backend,
+    /// e.g. `MessagePack`, `Bincode`, or `Json`.
+    type Codec: Codec;
+
+    /// Migrations for agent states stored by older versions of the simulation.
+    /// This is called once per `Population`, so it shouldn't do any heavy lifting.
+    fn migrations(&self) -> Migrations<Self> {
+        Migrations::new()
+    }
+
+    /// How agents are partitioned across workers. Defaults to `RangePartitioner`.
+    /// This is called once per run by the manager and each worker,
+    /// so it shouldn't do any heavy lifting (e.g. build the partitioner up front and share it).
+    fn partitioner(&self) -> Arc<Partitioner> {
+        Arc::new(RangePartitioner)
+    }
+
+    /// How agents are activated each step. Defaults to `Schedule::Synchronous`.
+    fn schedule(&self) -> Schedule {
+        Schedule::Synchronous
+    }
+
+    /// The stage an agent decides in, with `Schedule::Staged`.
+    /// This should not change within a step, e.g. as a result of an earlier stage's updates.
+    fn stage(&self, agent: &Agent<Self::State>) -> usize {
+        0
+    }
+
+    /// How often (in steps) an agent decides. Defaults to every step.
+    ///
+    /// Agents with the same interval are staggered by id, so they don't all act on the same steps.
+    /// This is ignored with `Schedule::DiscreteEvent`.
+    fn interval(&self, agent: &Agent<Self::State>) -> usize {
+        1
+    }
+
     /// Called whenever a new agent is spawned.
     /// You can use this to, for example, build an index of agents by state values.
-    fn on_spawns<R: Redis>(&self,
+    fn on_spawns<B: Backend>(&self,
                            agents: Vec<Agent<Self::State>>,
-                           population: &Population<Self, R>)
+                           population: &Population<Self, B>)
                            -> () {
     }
 
     /// Called whenever an agent is killed.
     /// You can use this to, for example, remove an agent from an index.
-    fn on_deaths<R: Redis>(&self,
+    fn on_deaths<B: Backend>(&self,
                            agents: Vec<Agent<Self::State>>,
-                           population: &Population<Self, R>)
+                           population: &Population<Self, B>)
                            -> () {
     }
 
     /// Computes updates for the specified agents and/or other agents.
-    fn decide<R: Redis>(&self,
+    fn decide<B: Backend>(&self,
                         agent: &Agent<Self::State>,
                         world: &Self::World,
-                        population: &Population<Self, R>,
+                        population: &Population<Self, B>,
                         updates: &mut Updates<Self>)
                         -> ();
 
@@ -62,9 +106,9 @@ pub trait Simulation: Sized + Send + Sync + Clone {
     fn update(&self, state: &mut Self::State, updates: Vec<Self::Update>) -> bool;
 
     /// Compute updates for the world.
-    fn world_decide<R: Redis>(&self,
+    fn world_decide<B: Backend>(&self,
                               world: &Self::World,
-                              population: &Population<Self, R>,
+                              population: &Population<Self, B>,
                               updates: &mut Updates<Self>)
                               -> () {
     }
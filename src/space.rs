@@ -0,0 +1,371 @@
+use std::cmp::Ordering;
+use ser::Codec;
+use sim::Simulation;
+use backend::{Backend, BackendOp};
+use compute::{Population, Updates, member, from_member};
+use error::{DjinnError, Result};
+
+/// A position in continuous space. Positions in a 2D space have `z = 0`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Point {
+    /// A 2D point.
+    pub fn new(x: f64, y: f64) -> Point {
+        Point { x: x, y: y, z: 0. }
+    }
+
+    /// A 3D point.
+    pub fn new3(x: f64, y: f64, z: f64) -> Point {
+        Point { x: x, y: y, z: z }
+    }
+
+    /// Euclidean distance to another point.
+    pub fn distance(&self, other: &Point) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt()
+    }
+}
+
+/// Bin coordinates.
+type Bin = (i64, i64, i64);
+
+/// A continuous 2D or 3D space that agents have real-valued positions in,
+/// stored in the backend alongside the population.
+///
+/// Agents are indexed by uniform bins of `bin_size`, so that neighbor queries
+/// only look at nearby bins. For the best performance, `bin_size` should be around
+/// the radius agents typically query. Changes made from `decide` (e.g. `queue_move`)
+/// are applied to the index in the update phase, like other updates.
+///
+/// As with a `Grid`, the space itself is just a description,
+/// so it can be kept in the simulation and queried through the `Population`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Space {
+    name: String,
+    bin_size: f64,
+    dims: usize,
+}
+
+impl Space {
+    /// A 2D space.
+    pub fn new(name: &str, bin_size: f64) -> Space {
+        Space {
+            name: name.to_owned(),
+            bin_size: bin_size,
+            dims: 2,
+        }
+    }
+
+    /// A 3D space.
+    pub fn new3(name: &str, bin_size: f64) -> Space {
+        Space {
+            name: name.to_owned(),
+            bin_size: bin_size,
+            dims: 3,
+        }
+    }
+
+    fn members_key(&self) -> String {
+        format!("space:{}:members", self.name)
+    }
+
+    fn position_key(&self, id: u64) -> String {
+        format!("space:{}:pos:{}", self.name, id)
+    }
+
+    fn bin_key(&self, bin: Bin) -> String {
+        format!("space:{}:bin:{}:{}:{}", self.name, bin.0, bin.1, bin.2)
+    }
+
+    fn bin(&self, pos: &Point) -> Bin {
+        let z = if self.dims == 3 { pos.z } else { 0. };
+        ((pos.x / self.bin_size).floor() as i64,
+         (pos.y / self.bin_size).floor() as i64,
+         (z / self.bin_size).floor() as i64)
+    }
+
+    /// The bins `ring` bins away (in each dimension) from a bin.
+    fn ring(&self, center: Bin, ring: i64) -> Vec<Bin> {
+        let dz = if self.dims == 3 { ring } else { 0 };
+        let mut bins = Vec::new();
+        for k in -dz..dz + 1 {
+            for j in -ring..ring + 1 {
+                for i in -ring..ring + 1 {
+                    if i.abs() == ring || j.abs() == ring || k.abs() == ring {
+                        bins.push((center.0 + i, center.1 + j, center.2 + k));
+                    }
+                }
+            }
+        }
+        bins
+    }
+
+    /// Get the ids and positions of the agents in some bins.
+    fn binned<S: Simulation, B: Backend>(&self,
+                                         population: &Population<S, B>,
+                                         bins: &[Bin])
+                                         -> Result<Vec<(u64, Point)>> {
+        let keys: Vec<String> = bins.iter().map(|bin| self.bin_key(*bin)).collect();
+        let mut ids = Vec::new();
+        for members in population.conn.smembers_multiple(&keys)? {
+            ids.extend(members.into_iter().map(from_member).collect::<Result<Vec<u64>>>()?);
+        }
+        self.positions(population, ids)
+    }
+
+    /// Get the ids and positions of all the agents in the space.
+    fn all<S: Simulation, B: Backend>(&self, population: &Population<S, B>) -> Result<Vec<(u64, Point)>> {
+        let ids = population.conn
+            .smembers(&self.members_key())?
+            .into_iter()
+            .map(from_member)
+            .collect::<Result<Vec<u64>>>()?;
+        self.positions(population, ids)
+    }
+
+    fn positions<S: Simulation, B: Backend>(&self,
+                                            population: &Population<S, B>,
+                                            ids: Vec<u64>)
+                                            -> Result<Vec<(u64, Point)>> {
+        let keys: Vec<String> = ids.iter().map(|id| self.position_key(*id)).collect();
+        let mut agents = Vec::with_capacity(ids.len());
+        for (id, data) in ids.into_iter().zip(population.conn.get_multiple(&keys)?) {
+            match data {
+                Some(data) => agents.push((id, S::Codec::decode(&data)?)),
+                None => return Err(DjinnError::MissingAgent(id)),
+            }
+        }
+        Ok(agents)
+    }
+
+    /// Get the position of an agent, if it's in the space.
+    pub fn position<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<Option<Point>> {
+        match population.conn.get(&self.position_key(id))? {
+            Some(data) => Ok(Some(S::Codec::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The number of agents in the space.
+    pub fn count<S: Simulation, B: Backend>(&self, population: &Population<S, B>) -> Result<usize> {
+        population.conn.scard(&self.members_key())
+    }
+
+    /// Get the ids and positions of the agents within `radius` of a point, sorted by id.
+    pub fn within<S: Simulation, B: Backend>(&self,
+                                             population: &Population<S, B>,
+                                             center: Point,
+                                             radius: f64)
+                                             -> Result<Vec<(u64, Point)>> {
+        let lo = self.bin(&Point::new3(center.x - radius, center.y - radius, center.z - radius));
+        let hi = self.bin(&Point::new3(center.x + radius, center.y + radius, center.z + radius));
+        let mut bins = Vec::new();
+        for k in lo.2..hi.2 + 1 {
+            for j in lo.1..hi.1 + 1 {
+                for i in lo.0..hi.0 + 1 {
+                    bins.push((i, j, k));
+                }
+            }
+        }
+        let mut agents: Vec<(u64, Point)> = self.binned(population, &bins)?
+            .into_iter()
+            .filter(|&(_, pos)| pos.distance(&center) <= radius)
+            .collect();
+        agents.sort_by_key(|&(id, _)| id);
+        Ok(agents)
+    }
+
+    /// Get the ids and positions of the other agents within `radius` of an agent, sorted by id.
+    pub fn neighbors_within<S: Simulation, B: Backend>(&self,
+                                                       population: &Population<S, B>,
+                                                       id: u64,
+                                                       radius: f64)
+                                                       -> Result<Vec<(u64, Point)>> {
+        let center = self.position(population, id)?.ok_or(DjinnError::MissingAgent(id))?;
+        let mut agents = self.within(population, center, radius)?;
+        agents.retain(|&(other, _)| other != id);
+        Ok(agents)
+    }
+
+    /// Get the ids and positions of the `k` agents nearest to a point,
+    /// nearest first (ties are broken by id).
+    pub fn nearest<S: Simulation, B: Backend>(&self,
+                                              population: &Population<S, B>,
+                                              center: Point,
+                                              k: usize)
+                                              -> Result<Vec<(u64, Point)>> {
+        self.nearest_except(population, center, k, None)
+    }
+
+    /// Get the ids and positions of the `k` other agents nearest to an agent,
+    /// nearest first (ties are broken by id).
+    pub fn k_nearest<S: Simulation, B: Backend>(&self,
+                                                population: &Population<S, B>,
+                                                id: u64,
+                                                k: usize)
+                                                -> Result<Vec<(u64, Point)>> {
+        let center = self.position(population, id)?.ok_or(DjinnError::MissingAgent(id))?;
+        self.nearest_except(population, center, k, Some(id))
+    }
+
+    fn nearest_except<S: Simulation, B: Backend>(&self,
+                                                 population: &Population<S, B>,
+                                                 center: Point,
+                                                 k: usize,
+                                                 except: Option<u64>)
+                                                 -> Result<Vec<(u64, Point)>> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let total = self.count(population)?;
+        let center_bin = self.bin(&center);
+        let by_distance = |a: &(f64, u64, Point), b: &(f64, u64, Point)| {
+            (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap_or(Ordering::Equal)
+        };
+
+        // search rings of bins outwards, until the `k`th nearest agent
+        // is closer than anything in the bins not yet searched
+        let mut found: Vec<(f64, u64, Point)> = Vec::new();
+        let mut ring = 0;
+        let mut seen = 0;
+        while seen < total {
+            let bins = self.ring(center_bin, ring);
+            if bins.len() > total {
+                // agents are sparse here, so looking at every agent
+                // is cheaper than searching any further out
+                found = self.all(population)?
+                    .into_iter()
+                    .filter(|&(id, _)| Some(id) != except)
+                    .map(|(id, pos)| (pos.distance(&center), id, pos))
+                    .collect();
+                found.sort_by(&by_distance);
+                break;
+            }
+            for (id, pos) in self.binned(population, &bins)? {
+                seen += 1;
+                if Some(id) != except {
+                    found.push((pos.distance(&center), id, pos));
+                }
+            }
+            found.sort_by(&by_distance);
+            if found.len() >= k && found[k - 1].0 <= ring as f64 * self.bin_size {
+                break;
+            }
+            ring += 1;
+        }
+        found.truncate(k);
+        Ok(found.into_iter().map(|(_, id, pos)| (id, pos)).collect())
+    }
+
+    /// Put an agent in the space, e.g. when setting it up.
+    pub fn place<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64, pos: Point) -> Result<()> {
+        population.conn.set(&self.position_key(id), S::Codec::encode(&pos)?)?;
+        population.conn.sadd(&self.bin_key(self.bin(&pos)), vec![member(id)])?;
+        population.conn.sadd(&self.members_key(), vec![member(id)])
+    }
+
+    /// Take an agent out of the space, if it's in it.
+    pub fn remove<S: Simulation, B: Backend>(&self, population: &Population<S, B>, id: u64) -> Result<()> {
+        if let Some(pos) = self.position(population, id)? {
+            population.conn.del(&self.position_key(id))?;
+            population.conn.srem(&self.bin_key(self.bin(&pos)), vec![member(id)])?;
+            population.conn.srem(&self.members_key(), vec![member(id)])?;
+        }
+        Ok(())
+    }
+
+    /// Queue putting an agent in the space, e.g. for a newly spawned agent.
+    pub fn queue_place<S: Simulation>(&self, updates: &mut Updates<S>, id: u64, pos: Point) -> Result<()> {
+        updates.queue_op(BackendOp::Set(self.position_key(id), S::Codec::encode(&pos)?));
+        updates.queue_op(BackendOp::Add(self.bin_key(self.bin(&pos)), member(id)));
+        updates.queue_op(BackendOp::Add(self.members_key(), member(id)));
+        Ok(())
+    }
+
+    /// Queue moving an agent from its current position (as of the start of the step) to another.
+    /// An agent should be moved at most once per step.
+    pub fn queue_move<S: Simulation, B: Backend>(&self,
+                                                 population: &Population<S, B>,
+                                                 updates: &mut Updates<S>,
+                                                 id: u64,
+                                                 to: Point)
+                                                 -> Result<()> {
+        let from = self.position(population, id)?.ok_or(DjinnError::MissingAgent(id))?;
+        updates.queue_op(BackendOp::Set(self.position_key(id), S::Codec::encode(&to)?));
+        let (from, to) = (self.bin(&from), self.bin(&to));
+        if from != to {
+            updates.queue_op(BackendOp::Remove(self.bin_key(from), member(id)));
+            updates.queue_op(BackendOp::Add(self.bin_key(to), member(id)));
+        }
+        Ok(())
+    }
+
+    /// Queue taking an agent out of the space, if it's in it, e.g. for an agent that's being killed.
+    pub fn queue_remove<S: Simulation, B: Backend>(&self,
+                                                   population: &Population<S, B>,
+                                                   updates: &mut Updates<S>,
+                                                   id: u64)
+                                                   -> Result<()> {
+        if let Some(pos) = self.position(population, id)? {
+            updates.queue_op(BackendOp::Del(self.position_key(id)));
+            updates.queue_op(BackendOp::Remove(self.bin_key(self.bin(&pos)), member(id)));
+            updates.queue_op(BackendOp::Remove(self.members_key(), member(id)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sim::Agent;
+    use backend::MemoryBackend;
+    use ser::MessagePack;
+
+    #[derive(Clone)]
+    struct Sim;
+
+    impl Simulation for Sim {
+        type State = ();
+        type World = ();
+        type Update = ();
+        type Codec = MessagePack;
+
+        fn decide<B: Backend>(&self, _: &Agent<()>, _: &(), _: &Population<Self, B>, _: &mut Updates<Self>) {}
+
+        fn update(&self, _: &mut (), _: Vec<()>) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn nearest_finds_far_away_agents() {
+        let population = Population::new(Sim, MemoryBackend::new());
+        let space = Space::new("test", 1.);
+        space.place(&population, 1, Point::new(0., 0.)).unwrap();
+        space.place(&population, 2, Point::new(1e6, 1e6)).unwrap();
+        space.place(&population, 3, Point::new(-1e6, 0.)).unwrap();
+        let nearest = space.nearest(&population, Point::new(0.5, 0.5), 2).unwrap();
+        assert_eq!(nearest.iter().map(|&(id, _)| id).collect::<Vec<u64>>(), vec![1, 3]);
+        let nearest = space.k_nearest(&population, 1, 5).unwrap();
+        assert_eq!(nearest.iter().map(|&(id, _)| id).collect::<Vec<u64>>(), vec![3, 2]);
+    }
+
+    #[test]
+    fn remove_looks_up_the_position() {
+        let population = Population::new(Sim, MemoryBackend::new());
+        let space = Space::new("test", 1.);
+        space.place(&population, 1, Point::new(3.5, 2.5)).unwrap();
+        space.place(&population, 2, Point::new(3.2, 2.1)).unwrap();
+        space.remove(&population, 1).unwrap();
+        space.remove(&population, 7).unwrap();
+        assert_eq!(space.count(&population).unwrap(), 1);
+        assert_eq!(space.position(&population, 1).unwrap(), None);
+        let within = space.within(&population, Point::new(3., 2.), 2.).unwrap();
+        assert_eq!(within.iter().map(|&(id, _)| id).collect::<Vec<u64>>(), vec![2]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+use compute::Population;
+use sim::Simulation;
+use backend::Backend;
+use error::Result;
+
+/// Why a run stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// The run went through all of its steps.
+    StepLimit,
+
+    /// A stop condition (by name) was met.
+    Condition(String),
+
+    /// In discrete-event mode, nothing more was due.
+    NoEvents,
+}
+
+/// What happened in a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    /// The step the run stopped at, i.e. the number of steps that were run,
+    /// including any run before resuming from a checkpoint.
+    pub step: usize,
+
+    pub reason: StopReason,
+}
+
+/// A condition, checked after each step, for ending a run early.
+pub enum StopCondition<S: Simulation, B: Backend> {
+    /// The population has died out.
+    Extinction,
+
+    /// The world hasn't changed for this many steps.
+    Convergence(usize),
+
+    /// The run has gone on for longer than this.
+    TimeLimit(Duration),
+
+    /// A user-defined predicate.
+    Custom(Box<Fn(usize, &S::World, &Population<S, B>) -> Result<bool> + Send>),
+}
+
+/// Tracks the progress of a run for checking stop conditions.
+pub struct RunProgress {
+    started: Instant,
+    unchanged: usize,
+}
+
+impl RunProgress {
+    pub fn new() -> RunProgress {
+        RunProgress {
+            started: Instant::now(),
+            unchanged: 0,
+        }
+    }
+
+    /// Record whether or not the world changed in the last step.
+    pub fn world_changed(&mut self, changed: bool) {
+        if changed {
+            self.unchanged = 0;
+        } else {
+            self.unchanged += 1;
+        }
+    }
+}
+
+impl<S: Simulation, B: Backend> StopCondition<S, B> {
+    /// Check if the condition is met after `step` steps.
+    pub fn is_met(&self,
+                  step: usize,
+                  world: &S::World,
+                  population: &Population<S, B>,
+                  progress: &RunProgress)
+                  -> Result<bool> {
+        match *self {
+            StopCondition::Extinction => Ok(population.count()? == 0),
+            StopCondition::Convergence(n_steps) => Ok(progress.unchanged >= n_steps),
+            StopCondition::TimeLimit(limit) => Ok(progress.started.elapsed() >= limit),
+            StopCondition::Custom(ref func) => func(step, world, population),
+        }
+    }
+}
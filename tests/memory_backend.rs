@@ -0,0 +1,262 @@
+extern crate djinn;
+#[macro_use]
+extern crate serde_derive;
+
+use std::sync::{Arc, Mutex};
+use djinn::{Agent, Simulation, Population, Updates, Backend, MemoryBackend, MessagePack, Manager,
+            Schedule, StopReason, run};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct State {
+    n: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct World {
+    ids: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Update {
+    Add(usize),
+}
+
+/// Each agent adds 1 to itself and 10 to the next agent in the ring.
+#[derive(Clone)]
+pub struct Ring;
+
+impl Simulation for Ring {
+    type State = State;
+    type Update = Update;
+    type World = World;
+    type Codec = MessagePack;
+
+    fn decide<B: Backend>(&self,
+                          agent: &Agent<State>,
+                          world: &World,
+                          _population: &Population<Self, B>,
+                          updates: &mut Updates<Self>) {
+        let i = world.ids.iter().position(|id| *id == agent.id).unwrap();
+        let next = world.ids[(i + 1) % world.ids.len()];
+        updates.queue(agent.id, Update::Add(1));
+        updates.queue(next, Update::Add(10));
+    }
+
+    fn update(&self, state: &mut State, updates: Vec<Update>) -> bool {
+        for update in updates {
+            match update {
+                Update::Add(n) => state.n += n,
+            }
+        }
+        true
+    }
+}
+
+/// Each agent counts up and goes to sleep at 2.
+#[derive(Clone)]
+pub struct Sleeper;
+
+impl Simulation for Sleeper {
+    type State = State;
+    type Update = Update;
+    type World = World;
+    type Codec = MessagePack;
+
+    fn decide<B: Backend>(&self,
+                          agent: &Agent<State>,
+                          _world: &World,
+                          _population: &Population<Self, B>,
+                          updates: &mut Updates<Self>) {
+        updates.queue(agent.id, Update::Add(1));
+        if agent.state.n >= 2 {
+            updates.sleep();
+        }
+    }
+
+    fn update(&self, state: &mut State, updates: Vec<Update>) -> bool {
+        Ring.update(state, updates)
+    }
+}
+
+/// Each agent counts up and dies at 2.
+#[derive(Clone)]
+pub struct Mortal;
+
+impl Simulation for Mortal {
+    type State = State;
+    type Update = Update;
+    type World = World;
+    type Codec = MessagePack;
+
+    fn decide<B: Backend>(&self,
+                          agent: &Agent<State>,
+                          _world: &World,
+                          _population: &Population<Self, B>,
+                          updates: &mut Updates<Self>) {
+        if agent.state.n >= 2 {
+            updates.kill(agent);
+        } else {
+            updates.queue(agent.id, Update::Add(1));
+        }
+    }
+
+    fn update(&self, state: &mut State, updates: Vec<Update>) -> bool {
+        Ring.update(state, updates)
+    }
+}
+
+/// Each agent adds 1 to a random agent.
+#[derive(Clone)]
+pub struct Picker;
+
+impl Simulation for Picker {
+    type State = State;
+    type Update = Update;
+    type World = World;
+    type Codec = MessagePack;
+
+    fn decide<B: Backend>(&self,
+                          _agent: &Agent<State>,
+                          _world: &World,
+                          population: &Population<Self, B>,
+                          updates: &mut Updates<Self>) {
+        let other = population.random("all").unwrap().unwrap();
+        updates.queue(other.id, Update::Add(1));
+    }
+
+    fn update(&self, state: &mut State, updates: Vec<Update>) -> bool {
+        Ring.update(state, updates)
+    }
+}
+
+/// Agents note down the order they decide in.
+#[derive(Clone)]
+pub struct Recorder {
+    schedule: Schedule,
+    order: Arc<Mutex<Vec<(usize, u64)>>>,
+}
+
+impl Simulation for Recorder {
+    type State = State;
+    type Update = Update;
+    type World = World;
+    type Codec = MessagePack;
+
+    fn decide<B: Backend>(&self,
+                          agent: &Agent<State>,
+                          _world: &World,
+                          _population: &Population<Self, B>,
+                          updates: &mut Updates<Self>) {
+        self.order.lock().unwrap().push((updates.step(), agent.id));
+    }
+
+    fn update(&self, _state: &mut State, _updates: Vec<Update>) -> bool {
+        false
+    }
+
+    fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+}
+
+fn spawn<S: Simulation<State = State>>(manager: &mut Manager<S, MemoryBackend>,
+                                       n: usize)
+                                       -> Vec<u64> {
+    manager.spawns((0..n).map(|_| State { n: 0 }).collect()).unwrap()
+}
+
+#[test]
+fn runs_to_completion() {
+    let mut manager = Manager::new(MemoryBackend::new(), Ring).unwrap();
+    let ids = spawn(&mut manager, 50);
+    let (manager, summary) = run(Ring, World { ids: ids.clone() }, manager, 3, 10).unwrap();
+    assert_eq!(summary.step, 10);
+    assert_eq!(summary.reason, StopReason::StepLimit);
+    assert_eq!(manager.population.count().unwrap(), 50);
+    for agent in manager.population.get_agents(ids).unwrap() {
+        assert_eq!(agent.state.n, 110);
+    }
+}
+
+#[test]
+fn sleepers_stay_asleep_after_resume() {
+    let path = std::env::temp_dir().join("djinn_memory_backend_sleep.ckpt");
+    let mut manager = Manager::new(MemoryBackend::new(), Sleeper).unwrap();
+    manager.set_checkpoints(1, &path);
+    let ids = spawn(&mut manager, 20);
+    let world = World { ids: ids.clone() };
+    let (manager, _) = run(Sleeper, world.clone(), manager, 2, 5).unwrap();
+    for agent in manager.population.get_agents(ids.clone()).unwrap() {
+        assert_eq!(agent.state.n, 3);
+    }
+
+    // resume on a fresh backend with a different slot layout
+    let mut manager = Manager::resume_from(MemoryBackend::new(), Sleeper, &path).unwrap();
+    manager.set_slots_per_worker(3);
+    let (manager, summary) = run(Sleeper, world, manager, 3, 9).unwrap();
+    assert_eq!(summary.step, 9);
+    for agent in manager.population.get_agents(ids).unwrap() {
+        assert_eq!(agent.state.n, 3);
+    }
+}
+
+#[test]
+fn extinction_stops_at_the_step_it_happens() {
+    let mut manager = Manager::new(MemoryBackend::new(), Mortal).unwrap();
+    manager.stop_on_extinction();
+    let ids = spawn(&mut manager, 10);
+    let (manager, summary) = run(Mortal, World { ids }, manager, 2, 10).unwrap();
+    assert_eq!(summary.step, 3);
+    assert_eq!(summary.reason, StopReason::Condition("extinction".to_owned()));
+    assert_eq!(manager.population.count().unwrap(), 0);
+}
+
+#[test]
+fn random_picks_dont_depend_on_worker_count() {
+    let states = |n_workers: usize| {
+        let mut manager = Manager::new(MemoryBackend::new(), Picker).unwrap();
+        manager.set_seed(42);
+        let ids = spawn(&mut manager, 30);
+        manager.population.indexes("all", ids.clone()).unwrap();
+        let (manager, _) = run(Picker, World { ids: ids.clone() }, manager, n_workers, 5).unwrap();
+        manager.population.get_agents(ids).unwrap()
+    };
+    let states = (states(1), states(3));
+    assert_eq!(states.0, states.1);
+    assert_eq!(states.0.iter().map(|a| a.state.n).sum::<usize>(), 30 * 5);
+}
+
+#[test]
+fn shuffled_schedules_shuffle_the_decide_order() {
+    let orders = |schedule: Schedule| {
+        let sim = Recorder {
+            schedule: schedule,
+            order: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut manager = Manager::new(MemoryBackend::new(), sim.clone()).unwrap();
+        manager.set_seed(42);
+        manager.set_slots_per_worker(1);
+        let ids = spawn(&mut manager, 20);
+        run(sim.clone(), World { ids }, manager, 1, 3).unwrap();
+        let order = sim.order.lock().unwrap();
+        (0..3)
+            .map(|step| order.iter().filter(|&&(s, _)| s == step).map(|&(_, id)| id).collect())
+            .collect::<Vec<Vec<u64>>>()
+    };
+
+    let synchronous = orders(Schedule::Synchronous);
+    for order in &synchronous {
+        assert_eq!(order.len(), 20);
+        assert!(order.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    let shuffled = orders(Schedule::Shuffled);
+    for order in &shuffled {
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, synchronous[0]);
+        assert!(order != &sorted);
+    }
+    assert!(shuffled[0] != shuffled[1]);
+    assert_eq!(shuffled, orders(Schedule::Shuffled));
+}